@@ -1,79 +1,1430 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
 use ink_lang as ink;
+pub use self::gravity::{Gravity, KeyType, Signature};
 
 #[ink::contract]
 mod gravity {
 
     use ink_storage::{
-        collections::{hashmap::Entry, HashMap},
+        collections::{hashmap::Entry, HashMap, Vec as StorageVec},
         traits::{PackedLayout, SpreadLayout},
     };
+    use ink_env::ReturnFlags;
     use web3::types::{Recovery,H160};
     use web3::signing::{ keccak256,recover};
+    use core::convert::TryFrom;
+    use std::mem::transmute;
+
+    #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub enum Error {
+        StaleRound,
+        NotEnoughSignatures,
+        InvalidSignature,
+        LengthMismatch,
+        InvalidThreshold,
+        InvalidSchedule,
+        NoActiveRound,
+        NotAConsul,
+        AlreadyRegistered,
+        NotRegistered,
+        NotOperator,
+        ConsulActive,
+        TransferFailed,
+        Overflow,
+    }
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    /// Signature of one consul. `Ed25519` and `Sr25519` consuls sign with `r || s`
+    /// and leave `v` as zero.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct Signature {
+        pub v: u64,
+        pub r: [u8;32],
+        pub s: [u8;32],
+    }
+
+    /// How many consul signatures are needed to authorize a change.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode, SpreadLayout, PackedLayout)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
+    )]
+    pub enum Threshold {
+        /// a fixed number of signatures
+        Absolute(u128),
+        /// a share of the consul set, rounded up, so it follows the set as it grows or shrinks
+        Fraction { numerator: u32, denominator: u32 },
+    }
+
+    /// Signature scheme of a consul key.
+    /// `Secp256k1` consuls keep their ethereum address in the first 20 bytes of the entry,
+    /// `Ed25519` and `Sr25519` consuls are the 32-byte public key itself.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode, SpreadLayout, PackedLayout)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
+    )]
+    pub enum KeyType {
+        Secp256k1,
+        Ed25519,
+        Sr25519,
+    }
+
+    /// Blocks during which the consuls of a round may authorize changes.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode, SpreadLayout, PackedLayout)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
+    )]
+    pub struct RoundSchedule {
+        pub activation_block: BlockNumber,
+        /// first block at which the round is no longer valid, `None` means it never expires
+        pub expiry_block: Option<BlockNumber>,
+        /// blocks after `expiry_block` during which the consuls may still rotate to a new set,
+        /// if the round expired without a successor
+        pub recovery_blocks: BlockNumber,
+    }
+
+    impl RoundSchedule {
+        fn is_active_at(&self, block: BlockNumber) -> bool {
+            self.activation_block <= block && self.expiry_block.map_or(true, |expiry| block < expiry)
+        }
+
+        /// whether `block` is past the expiry but still within the recovery blocks
+        fn is_recoverable_at(&self, block: BlockNumber) -> bool {
+            self.expiry_block.map_or(false, |expiry| {
+                expiry <= block && block < expiry.saturating_add(self.recovery_blocks)
+            })
+        }
+    }
+
+    /// Registry entry of a consul key.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode, SpreadLayout, PackedLayout)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
+    )]
+    pub struct ConsulInfo {
+        /// account that runs the consul and manages its entry
+        pub operator: AccountId,
+        /// balance bonded by the operator, returned when the entry is removed
+        pub stake: Balance,
+        /// reputation set by the consuls, higher is better
+        pub score: u64,
+        /// round the consul was active in when it registered
+        pub join_round: u64,
+    }
+
+    /// name and version of the EIP-712 signing domain
+    const DOMAIN_NAME: &[u8] = b"Gravity";
+    const DOMAIN_VERSION: &[u8] = b"1";
+
+    /// signing context substrate uses for sr25519 signatures
+    const SR25519_SIGNING_CONTEXT: &[u8] = b"substrate";
+
+    impl Threshold {
+        pub fn required_signatures(&self, consuls_count: usize) -> u128 {
+            match *self {
+                Threshold::Absolute(value) => value,
+                Threshold::Fraction { numerator, denominator } => {
+                    let product = consuls_count as u128 * numerator as u128;
+                    (product + denominator as u128 - 1) / denominator as u128
+                }
+            }
+        }
+
+        /// a threshold is valid if it needs at least one and at most all of the consuls
+        fn is_valid_for(&self, consuls_count: usize) -> bool {
+            match *self {
+                Threshold::Absolute(value) => value > 0 && value <= consuls_count as u128,
+                Threshold::Fraction { numerator, denominator } => {
+                    numerator > 0 && numerator <= denominator && consuls_count > 0
+                }
+            }
+        }
+    }
+
+    #[ink(event)]
+    pub struct ConsulsUpdated {
+        #[ink(topic)]
+        round_id: u64,
+        consuls: Vec<[u8;32]>,
+        activation_block: BlockNumber,
+        expiry_block: Option<BlockNumber>,
+        recovery_blocks: BlockNumber,
+    }
+
+    #[ink(event)]
+    pub struct ThresholdUpdated {
+        #[ink(topic)]
+        round_id: u64,
+        threshold: Threshold,
+    }
+
+    #[ink(event)]
+    pub struct ConsulRegistered {
+        #[ink(topic)]
+        consul: [u8;32],
+        #[ink(topic)]
+        operator: AccountId,
+        stake: Balance,
+    }
+
+    #[ink(event)]
+    pub struct ConsulInfoUpdated {
+        #[ink(topic)]
+        consul: [u8;32],
+        info: ConsulInfo,
+    }
+
+    #[ink(event)]
+    pub struct ConsulUnregistered {
+        #[ink(topic)]
+        consul: [u8;32],
+        #[ink(topic)]
+        operator: AccountId,
+        stake: Balance,
+    }
+
     #[ink(storage)]
     pub struct Gravity {
-        consuls_by_rounds: HashMap<[u8;32],Vec<[u8;32]>>,
-        benefitial_value: u128,
-        last_round: [u8;32],
+        consuls_by_rounds: HashMap<u64,Vec<[u8;32]>>,
+        /// threshold each round was authorized with, so older rounds keep their own
+        thresholds_by_rounds: HashMap<u64,Threshold>,
+        /// `key_types_by_rounds[round][i]` is the key type of `consuls_by_rounds[round][i]`
+        key_types_by_rounds: HashMap<u64,Vec<KeyType>>,
+        schedules_by_rounds: HashMap<u64,RoundSchedule>,
+        last_round: u64,
+        /// every stored round id in ascending order
+        rounds: StorageVec<u64>,
+        /// id of the chain this deployment lives on, part of every signed change
+        chain_id: u64,
+        consul_infos: HashMap<[u8;32],ConsulInfo>,
+        /// nonce of the next score update, so a signed update can only be applied once
+        score_nonce: u64,
     }
 
     impl Gravity {
         #[ink(constructor)]
-        pub fn new(consuls: Vec<[u8;32]>, benefitial_value: u128) -> Self {
-            let mut hm: HashMap<[u8;32],Vec<[u8;32]>> = HashMap::new();
-            hm.insert([0u8;32], consuls);
+        pub fn new(consuls: Vec<[u8;32]>, benefitial_value: u128, chain_id: u64) -> Self {
+            let key_types = vec![KeyType::Secp256k1; consuls.len()];
+            Self::new_with_key_types(consuls, key_types, benefitial_value, chain_id)
+        }
+
+        #[ink(constructor)]
+        pub fn new_with_key_types(consuls: Vec<[u8;32]>, key_types: Vec<KeyType>, benefitial_value: u128, chain_id: u64) -> Self {
+            assert_eq!(consuls.len(), key_types.len(), "every consul needs a key type");
+            let threshold = Threshold::Absolute(benefitial_value);
+            assert!(threshold.is_valid_for(consuls.len()), "the threshold has to be reachable by the consuls");
+            let mut key_types_by_rounds: HashMap<u64,Vec<KeyType>> = HashMap::new();
+            key_types_by_rounds.insert(0, key_types);
+            let mut hm: HashMap<u64,Vec<[u8;32]>> = HashMap::new();
+            hm.insert(0, consuls);
+            let mut thresholds: HashMap<u64,Threshold> = HashMap::new();
+            thresholds.insert(0, threshold);
+            let mut schedules: HashMap<u64,RoundSchedule> = HashMap::new();
+            schedules.insert(0, RoundSchedule {
+                activation_block: Self::env().block_number(),
+                expiry_block: None,
+                recovery_blocks: 0,
+            });
+            let mut rounds = StorageVec::new();
+            rounds.push(0);
             Self { 
-                last_round: [0u8;32],
+                last_round: 0,
+                rounds: rounds,
                 consuls_by_rounds: hm,
-                benefitial_value: benefitial_value,
+                thresholds_by_rounds: thresholds,
+                key_types_by_rounds: key_types_by_rounds,
+                schedules_by_rounds: schedules,
+                chain_id: chain_id,
+                consul_infos: HashMap::new(),
+                score_nonce: 0,
             }
         }
 
+        /// Rotates the consuls to a set of `Secp256k1` consuls, see `update_consuls_with_key_types`.
         #[ink(message)]
-        pub fn update_consuls(&mut self, new_consuls: Vec<[u8;32]>, v: Vec<u64>, r: Vec<[u8;32]>, s: Vec<[u8;32]>, round_id: [u8;32]) {
-            if round_id < self.last_round { return; }
-            let hash = self.hash_new_consuls(new_consuls.clone(),round_id);
-            let mut count: u128 = 0;
-            for i in 0..new_consuls.len() {
-                let rec = Recovery::new(hash, v[i], r[i].into(), s[i].into())
-                    .as_signature()
-                    .unwrap();
-                let addr = recover(&hash, &rec.0, rec.1).unwrap();
-                let cons_addr = H160::from_slice(&new_consuls[i][0..20]);
-                if addr == cons_addr { count += 1; }
+        pub fn update_consuls(
+            &mut self,
+            new_consuls: Vec<[u8;32]>,
+            signers: Vec<u8>,
+            signatures: Vec<Signature>,
+            round_id: u64,
+            activation_block: BlockNumber,
+            expiry_block: Option<BlockNumber>,
+            recovery_blocks: BlockNumber,
+        ) -> Result<()> {
+            let new_key_types = vec![KeyType::Secp256k1; new_consuls.len()];
+            self.update_consuls_with_key_types(new_consuls, new_key_types, signers, signatures, round_id, activation_block, expiry_block, recovery_blocks)
+        }
+
+        /// Rotates the consuls, the rotation has to be signed by the consuls active at the current
+        /// block and `round_id` has to be greater than `last_round`. The new round keeps the
+        /// threshold of `last_round` and becomes active at `activation_block`, which can not be
+        /// earlier than the activation of `last_round` nor later than its expiry.
+        /// If `last_round` expired without a successor, its consuls may still sign a rotation
+        /// that activates at the current block during the `recovery_blocks` of its schedule.
+        /// Once those passed no consuls can sign anymore and a new deployment is needed, so
+        /// rounds with an expiry should leave enough recovery blocks to react to a lapse.
+        /// `signers` is a bitmap of the active consuls that signed, bit `i % 8` of byte `i / 8`
+        /// stands for `consuls[i]`, and `signatures` holds their signatures in the same order.
+        #[ink(message)]
+        pub fn update_consuls_with_key_types(
+            &mut self,
+            new_consuls: Vec<[u8;32]>,
+            new_key_types: Vec<KeyType>,
+            signers: Vec<u8>,
+            signatures: Vec<Signature>,
+            round_id: u64,
+            activation_block: BlockNumber,
+            expiry_block: Option<BlockNumber>,
+            recovery_blocks: BlockNumber,
+        ) -> Result<()> {
+            if round_id <= self.last_round { return Err(Error::StaleRound); }
+            if new_consuls.len() != new_key_types.len() { return Err(Error::LengthMismatch); }
+            let threshold = self.last_threshold();
+            if !threshold.is_valid_for(new_consuls.len()) { return Err(Error::InvalidThreshold); }
+            let schedule = RoundSchedule {
+                activation_block: activation_block,
+                expiry_block: expiry_block,
+                recovery_blocks: recovery_blocks,
+            };
+            self.ensure_valid_schedule(schedule)?;
+            let hash = self.hash_new_consuls_with_key_types(
+                new_consuls.clone(),
+                new_key_types.clone(),
+                round_id,
+                activation_block,
+                expiry_block,
+                recovery_blocks,
+            );
+            let authorizing_round = self.rotation_round()?;
+            self.verify_round_consuls(authorizing_round, hash, &signers, &signatures)?;
+            self.add_round(round_id, new_consuls.clone(), new_key_types, threshold, schedule);
+            self.env().emit_event(ConsulsUpdated {
+                round_id: round_id,
+                consuls: new_consuls,
+                activation_block: activation_block,
+                expiry_block: expiry_block,
+                recovery_blocks: recovery_blocks,
+            });
+            Ok(())
+        }
+
+        /// A new round can not start in the past or before `last_round`, and has to start before
+        /// it ends. A round starting after the expiry of `last_round` would leave blocks without
+        /// an active round, so it is rejected unless `last_round` already expired and it starts now.
+        fn ensure_valid_schedule(&self, schedule: RoundSchedule) -> Result<()> {
+            let now = self.env().block_number();
+            let last_schedule = self.last_schedule();
+            if schedule.activation_block < now || schedule.activation_block < last_schedule.activation_block {
+                return Err(Error::InvalidSchedule);
+            }
+            if let Some(last_expiry) = last_schedule.expiry_block {
+                if schedule.activation_block > core::cmp::max(last_expiry, now) {
+                    return Err(Error::InvalidSchedule);
+                }
+            }
+            if schedule.expiry_block.map_or(false, |expiry| expiry <= schedule.activation_block) {
+                return Err(Error::InvalidSchedule);
+            }
+            Ok(())
+        }
+
+        fn last_schedule(&self) -> RoundSchedule {
+            self.get_round_schedule(self.last_round)
+                .expect("every stored round has a schedule")
+        }
+
+        /// threshold of `last_round`, which the next rotation keeps
+        fn last_threshold(&self) -> Threshold {
+            self.get_threshold_by_round_id(self.last_round)
+                .expect("every stored round has a threshold")
+        }
+
+        /// The round whose consuls sign a rotation: the active round, or `last_round` during
+        /// its recovery blocks. Other changes can only be signed by the active round.
+        fn rotation_round(&self) -> Result<u64> {
+            if let Some(round_id) = self.get_active_round() {
+                return Ok(round_id);
+            }
+            let now = self.env().block_number();
+            if self.last_schedule().is_recoverable_at(now) {
+                Ok(self.last_round)
+            } else {
+                Err(Error::NoActiveRound)
             }
-            if count < self.benefitial_value { return; }
-            self.consuls_by_rounds.insert(round_id, new_consuls);
+        }
+
+        /// Changes the threshold, signed by the active consuls under their threshold.
+        /// The change starts a new round with the consuls and expiry of `last_round`, so the
+        /// rounds before it are still verified with the threshold they had. Like a rotation it
+        /// emits `ConsulsUpdated` for the new round, so every round id shows up in that event.
+        #[ink(message)]
+        pub fn update_threshold(&mut self, threshold: Threshold, signers: Vec<u8>, signatures: Vec<Signature>, round_id: u64) -> Result<()> {
+            if round_id <= self.last_round { return Err(Error::StaleRound); }
+            let consuls = self.get_consuls_by_round_id(self.last_round);
+            if !threshold.is_valid_for(consuls.len()) { return Err(Error::InvalidThreshold); }
+            let hash = self.hash_new_threshold(threshold, round_id);
+            self.verify_active_consuls(hash, &signers, &signatures)?;
+            let key_types = self.get_key_types_by_round_id(self.last_round);
+            let last_schedule = self.last_schedule();
+            let schedule = RoundSchedule {
+                activation_block: core::cmp::max(self.env().block_number(), last_schedule.activation_block),
+                expiry_block: last_schedule.expiry_block,
+                recovery_blocks: last_schedule.recovery_blocks,
+            };
+            self.add_round(round_id, consuls.clone(), key_types, threshold, schedule);
+            self.env().emit_event(ConsulsUpdated {
+                round_id: round_id,
+                consuls: consuls,
+                activation_block: schedule.activation_block,
+                expiry_block: schedule.expiry_block,
+                recovery_blocks: schedule.recovery_blocks,
+            });
+            self.env().emit_event(ThresholdUpdated {
+                round_id: round_id,
+                threshold: threshold,
+            });
+            Ok(())
+        }
+
+        fn add_round(&mut self, round_id: u64, consuls: Vec<[u8;32]>, key_types: Vec<KeyType>, threshold: Threshold, schedule: RoundSchedule) {
+            self.consuls_by_rounds.insert(round_id, consuls);
+            self.schedules_by_rounds.insert(round_id, schedule);
+            self.key_types_by_rounds.insert(round_id, key_types);
+            self.thresholds_by_rounds.insert(round_id, threshold);
             self.last_round = round_id;
+            self.rounds.push(round_id);
+        }
+
+        /// Registers a consul of the active set, the caller becomes its operator and the
+        /// transferred balance its stake. `signature` is the consul key's signature of
+        /// `hash_consul_registration(consul, caller)`, so nobody can claim another operator's key.
+        /// Errors revert the call, so the transferred balance goes back to the caller.
+        #[ink(message, payable)]
+        pub fn register_consul(&mut self, consul: [u8;32], signature: Signature) -> Result<()> {
+            match self.try_register_consul(consul, signature) {
+                Ok(()) => Ok(()),
+                Err(err) => Self::revert(err),
+            }
+        }
+
+        fn try_register_consul(&mut self, consul: [u8;32], signature: Signature) -> Result<()> {
+            if self.consul_infos.get(&consul).is_some() { return Err(Error::AlreadyRegistered); }
+            let round_id = self.get_active_round().ok_or(Error::NotAConsul)?;
+            let index = self.get_consuls_by_round_id(round_id)
+                .iter()
+                .position(|c| *c == consul)
+                .ok_or(Error::NotAConsul)?;
+            let key_type = self.get_key_types_by_round_id(round_id)[index];
+            let operator = self.env().caller();
+            let hash = self.hash_consul_registration(consul, operator);
+            Self::verify_signature(key_type, consul, hash, &signature)?;
+
+            let stake = self.env().transferred_balance();
+            self.consul_infos.insert(consul, ConsulInfo {
+                operator: operator,
+                stake: stake,
+                score: 0,
+                join_round: round_id,
+            });
+            self.env().emit_event(ConsulRegistered {
+                consul: consul,
+                operator: operator,
+                stake: stake,
+            });
+            Ok(())
+        }
+
+        /// Adds the transferred balance to the stake of a consul, only its operator can bond.
+        /// Errors revert the call like in `register_consul`.
+        #[ink(message, payable)]
+        pub fn bond(&mut self, consul: [u8;32]) -> Result<()> {
+            match self.try_bond(consul) {
+                Ok(()) => Ok(()),
+                Err(err) => Self::revert(err),
+            }
         }
 
+        fn try_bond(&mut self, consul: [u8;32]) -> Result<()> {
+            let mut info = self.operated_consul(consul)?;
+            info.stake = info.stake
+                .checked_add(self.env().transferred_balance())
+                .ok_or(Error::Overflow)?;
+            self.set_consul_info(consul, info);
+            Ok(())
+        }
+
+        /// Hands the entry of a consul over to another operator.
         #[ink(message)]
-        pub fn hash_new_consuls(&self, new_consuls: Vec<[u8;32]>, round_id: [u8;32]) -> [u8;32] {
-            let mut new_consuls = new_consuls; 
-            new_consuls.push(round_id);
+        pub fn set_operator(&mut self, consul: [u8;32], new_operator: AccountId) -> Result<()> {
+            let mut info = self.operated_consul(consul)?;
+            info.operator = new_operator;
+            self.set_consul_info(consul, info);
+            Ok(())
+        }
+
+        /// Removes the entry of a consul that is neither in the active set nor in a scheduled one
+        /// and returns its stake to the operator.
+        #[ink(message)]
+        pub fn unregister_consul(&mut self, consul: [u8;32]) -> Result<()> {
+            let info = self.operated_consul(consul)?;
+            if self.is_current_or_scheduled_consul(consul) { return Err(Error::ConsulActive); }
+            if info.stake > 0 {
+                self.env().transfer(info.operator, info.stake).map_err(|_| Error::TransferFailed)?;
+            }
+            self.consul_infos.take(&consul);
+            self.env().emit_event(ConsulUnregistered {
+                consul: consul,
+                operator: info.operator,
+                stake: info.stake,
+            });
+            Ok(())
+        }
+
+        /// Sets the score of a registered consul, signed by the active consuls under their threshold.
+        #[ink(message)]
+        pub fn update_consul_score(&mut self, consul: [u8;32], score: u64, signers: Vec<u8>, signatures: Vec<Signature>) -> Result<()> {
+            let mut info = self.get_consul_info(consul).ok_or(Error::NotRegistered)?;
+            let hash = self.hash_consul_score(consul, score, self.score_nonce);
+            self.verify_active_consuls(hash, &signers, &signatures)?;
+            self.score_nonce += 1;
+            info.score = score;
+            self.set_consul_info(consul, info);
+            Ok(())
+        }
+
+        /// whether `consul` is in the latest activated round or in a round scheduled after it
+        fn is_current_or_scheduled_consul(&self, consul: [u8;32]) -> bool {
+            let now = self.env().block_number();
+            let activated = self.rounds_partition_point(|round_id| {
+                self.get_round_schedule(round_id)
+                    .map_or(false, |schedule| schedule.activation_block <= now)
+            });
+            (activated.saturating_sub(1)..self.rounds.len()).any(|i| {
+                self.consuls_by_rounds
+                    .get(&self.rounds[i])
+                    .map_or(false, |consuls| consuls.contains(&consul))
+            })
+        }
+
+        /// the payable messages fail through this, like `Token::revert` does
+        fn revert(err: Error) -> ! {
+            ink_env::return_value::<Result<()>>(ReturnFlags::default().set_reverted(true), &Err(err))
+        }
+
+        /// entry of a registered consul operated by the caller
+        fn operated_consul(&self, consul: [u8;32]) -> Result<ConsulInfo> {
+            let info = self.get_consul_info(consul).ok_or(Error::NotRegistered)?;
+            if info.operator != self.env().caller() { return Err(Error::NotOperator); }
+            Ok(info)
+        }
+
+        fn set_consul_info(&mut self, consul: [u8;32], info: ConsulInfo) {
+            self.consul_infos.insert(consul, info);
+            self.env().emit_event(ConsulInfoUpdated {
+                consul: consul,
+                info: info,
+            });
+        }
+
+        /// Checks that enough consuls of the round active at the current block signed `hash`,
+        /// with the same bitmap layout as `update_consuls_with_key_types`. Contracts relying on
+        /// the consuls, like Nebula, call it so they accept every key type Gravity does.
+        #[ink(message)]
+        pub fn verify_consuls(&self, hash: [u8;32], signers: Vec<u8>, signatures: Vec<Signature>) -> Result<()> {
+            self.verify_active_consuls(hash, &signers, &signatures)
+        }
+
+        /// checks that enough consuls of the round active at the current block signed `hash`
+        fn verify_active_consuls(&self, hash: [u8;32], signers: &[u8], signatures: &[Signature]) -> Result<()> {
+            let round_id = self.get_active_round().ok_or(Error::NoActiveRound)?;
+            self.verify_round_consuls(round_id, hash, signers, signatures)
+        }
+
+        /// Checks that enough consuls of `round_id` signed `hash` under its threshold.
+        /// The bitmap and the signatures are validated before any signature is checked, and
+        /// checking stops as soon as the threshold is reached.
+        fn verify_round_consuls(&self, round_id: u64, hash: [u8;32], signers: &[u8], signatures: &[Signature]) -> Result<()> {
+            let consuls = self.consuls_by_rounds
+                .get(&round_id)
+                .cloned()
+                .unwrap_or_default();
+            let key_types = self.get_key_types_by_round_id(round_id);
+            let threshold = self.get_threshold_by_round_id(round_id)
+                .expect("every stored round has a threshold");
+            let signer_indexes = Self::signer_indexes(signers, consuls.len())?;
+            if signer_indexes.len() != signatures.len() {
+                return Err(Error::LengthMismatch);
+            }
+
+            let required = threshold.required_signatures(consuls.len());
+            let mut verified: Vec<(KeyType, [u8;32])> = Vec::new();
+            for (&i, signature) in signer_indexes.iter().zip(signatures.iter()) {
+                let signer = Self::signer_key(key_types[i], consuls[i]);
+                // a consul listed several times is counted once
+                if verified.contains(&signer) { continue; }
+                Self::verify_signature(key_types[i], consuls[i], hash, signature)?;
+                verified.push(signer);
+                if verified.len() as u128 >= required { return Ok(()); }
+            }
+            Err(Error::NotEnoughSignatures)
+        }
+
+        /// indexes of the set bits of `signers`, which has to be exactly as long as needed for
+        /// `consuls_count` bits, with no bits set past the last consul
+        fn signer_indexes(signers: &[u8], consuls_count: usize) -> Result<Vec<usize>> {
+            if signers.len() != (consuls_count + 7) / 8 {
+                return Err(Error::LengthMismatch);
+            }
+            let mut indexes = Vec::new();
+            for (byte_index, byte) in signers.iter().enumerate() {
+                for bit in 0..8 {
+                    if byte & (1 << bit) == 0 { continue; }
+                    let index = byte_index * 8 + bit;
+                    if index >= consuls_count { return Err(Error::LengthMismatch); }
+                    indexes.push(index);
+                }
+            }
+            Ok(indexes)
+        }
+
+        /// the part of a consul entry that identifies the signer
+        fn signer_key(key_type: KeyType, consul: [u8;32]) -> (KeyType, [u8;32]) {
+            let mut key = consul;
+            if key_type == KeyType::Secp256k1 {
+                for b in key[20..].iter_mut() { *b = 0; }
+            }
+            (key_type, key)
+        }
+
+        /// ink_env 3.0.0-rc2 has no ed25519 or sr25519 verification, so they are checked by
+        /// pure rust verifiers built without std, the same way web3 is used for secp256k1.
+        /// ed25519 is verified strictly, so malleable signatures are rejected.
+        fn verify_signature(key_type: KeyType, consul: [u8;32], hash: [u8;32], signature: &Signature) -> Result<()> {
+            let mut sig = [0u8;64];
+            sig[0..32].copy_from_slice(&signature.r);
+            sig[32..64].copy_from_slice(&signature.s);
+            let valid = match key_type {
+                KeyType::Secp256k1 => {
+                    let rec = Recovery::new(hash, signature.v, signature.r.into(), signature.s.into())
+                        .as_signature()
+                        .ok_or(Error::InvalidSignature)?;
+                    let addr = recover(&hash, &rec.0, rec.1).map_err(|_| Error::InvalidSignature)?;
+                    addr == H160::from_slice(&consul[0..20])
+                }
+                KeyType::Ed25519 => {
+                    let public = ed25519_dalek::PublicKey::from_bytes(&consul)
+                        .map_err(|_| Error::InvalidSignature)?;
+                    let sig = ed25519_dalek::Signature::try_from(&sig[..])
+                        .map_err(|_| Error::InvalidSignature)?;
+                    public.verify_strict(&hash, &sig).is_ok()
+                }
+                KeyType::Sr25519 => {
+                    let public = schnorrkel::PublicKey::from_bytes(&consul)
+                        .map_err(|_| Error::InvalidSignature)?;
+                    let sig = schnorrkel::Signature::from_bytes(&sig)
+                        .map_err(|_| Error::InvalidSignature)?;
+                    public.verify_simple(SR25519_SIGNING_CONTEXT, &hash, &sig).is_ok()
+                }
+            };
+            if !valid { return Err(Error::InvalidSignature); }
+            Ok(())
+        }
+
+        /// packs a number as a big-endian uint256
+        fn uint256(value: u128) -> [u8;32] {
+            let mut packed = [0u8;32];
+            packed[16..32].copy_from_slice(&value.to_be_bytes());
+            packed
+        }
+
+        #[ink(message)]
+        pub fn get_chain_id(&self) -> u64 {
+            self.chain_id
+        }
+
+        /// EIP-712 domain separator, binds every signed change to this contract on this chain
+        #[ink(message)]
+        pub fn domain_separator(&self) -> [u8;32] {
+            let contract: [u8;32] = unsafe { transmute(self.env().account_id()) };
+            let mut data: Vec<u8> = Vec::new();
+            data.extend_from_slice(&keccak256(b"EIP712Domain(string name,string version,uint256 chainId,bytes32 verifyingContract)"));
+            data.extend_from_slice(&keccak256(DOMAIN_NAME));
+            data.extend_from_slice(&keccak256(DOMAIN_VERSION));
+            data.extend_from_slice(&Self::uint256(u128::from(self.chain_id)));
+            data.extend_from_slice(&contract);
+            keccak256(&data[..])
+        }
+
+        /// the digest consuls sign for a typed struct hash
+        fn hash_typed_data(&self, struct_hash: [u8;32]) -> [u8;32] {
             let mut data: Vec<u8> = Vec::new();
+            data.extend_from_slice(b"\x19\x01");
+            data.extend_from_slice(&self.domain_separator());
+            data.extend_from_slice(&struct_hash);
+            keccak256(&data[..])
+        }
+
+        /// Same as `hash_new_consuls_with_key_types` for a `Secp256k1` only set.
+        #[ink(message)]
+        pub fn hash_new_consuls(
+            &self,
+            new_consuls: Vec<[u8;32]>,
+            round_id: u64,
+            activation_block: BlockNumber,
+            expiry_block: Option<BlockNumber>,
+            recovery_blocks: BlockNumber,
+        ) -> [u8;32] {
+            let new_key_types = vec![KeyType::Secp256k1; new_consuls.len()];
+            self.hash_new_consuls_with_key_types(new_consuls, new_key_types, round_id, activation_block, expiry_block, recovery_blocks)
+        }
+
+        /// EIP-712 hash of
+        /// `Rotation(bytes32[] consuls,uint8[] keyTypes,uint256 round,uint256 activationBlock,uint256 expiryBlock,uint256 recoveryBlocks)`,
+        /// a round without expiry has `expiryBlock` zero
+        #[ink(message)]
+        pub fn hash_new_consuls_with_key_types(
+            &self,
+            new_consuls: Vec<[u8;32]>,
+            new_key_types: Vec<KeyType>,
+            round_id: u64,
+            activation_block: BlockNumber,
+            expiry_block: Option<BlockNumber>,
+            recovery_blocks: BlockNumber,
+        ) -> [u8;32] {
+            let mut consuls_data: Vec<u8> = Vec::new();
             for i in new_consuls.iter() {
-                data.append(&mut Vec::from(&i[..]));
+                consuls_data.append(&mut Vec::from(&i[..]));
             }
-            keccak256(&data[..])
+            let mut key_types_data: Vec<u8> = Vec::new();
+            for key_type in new_key_types.iter() {
+                key_types_data.extend_from_slice(&Self::uint256(*key_type as u128));
+            }
+
+            let mut data: Vec<u8> = Vec::new();
+            data.extend_from_slice(&keccak256(
+                b"Rotation(bytes32[] consuls,uint8[] keyTypes,uint256 round,uint256 activationBlock,uint256 expiryBlock,uint256 recoveryBlocks)"
+            ));
+            data.extend_from_slice(&keccak256(&consuls_data[..]));
+            data.extend_from_slice(&keccak256(&key_types_data[..]));
+            data.extend_from_slice(&Self::uint256(u128::from(round_id)));
+            data.extend_from_slice(&Self::uint256(u128::from(activation_block)));
+            data.extend_from_slice(&Self::uint256(expiry_block.map_or(0, u128::from)));
+            data.extend_from_slice(&Self::uint256(u128::from(recovery_blocks)));
+            self.hash_typed_data(keccak256(&data[..]))
+        }
+
+        /// EIP-712 hash of
+        /// `ThresholdUpdate(uint8 kind,uint256 value,uint32 numerator,uint32 denominator,uint256 round)`,
+        /// `kind` is 0 for `Absolute` and 1 for `Fraction`, fields of the other kind are zero
+        #[ink(message)]
+        pub fn hash_new_threshold(&self, threshold: Threshold, round_id: u64) -> [u8;32] {
+            let (kind, value, numerator, denominator) = match threshold {
+                Threshold::Absolute(value) => (0, value, 0, 0),
+                Threshold::Fraction { numerator, denominator } => (1, 0, numerator, denominator),
+            };
+            let mut data: Vec<u8> = Vec::new();
+            data.extend_from_slice(&keccak256(
+                b"ThresholdUpdate(uint8 kind,uint256 value,uint32 numerator,uint32 denominator,uint256 round)"
+            ));
+            data.extend_from_slice(&Self::uint256(kind));
+            data.extend_from_slice(&Self::uint256(value));
+            data.extend_from_slice(&Self::uint256(u128::from(numerator)));
+            data.extend_from_slice(&Self::uint256(u128::from(denominator)));
+            data.extend_from_slice(&Self::uint256(u128::from(round_id)));
+            self.hash_typed_data(keccak256(&data[..]))
+        }
+
+        /// EIP-712 hash of `Registration(bytes32 consul,bytes32 operator)`
+        #[ink(message)]
+        pub fn hash_consul_registration(&self, consul: [u8;32], operator: AccountId) -> [u8;32] {
+            let operator: [u8;32] = unsafe { transmute(operator) };
+            let mut data: Vec<u8> = Vec::new();
+            data.extend_from_slice(&keccak256(b"Registration(bytes32 consul,bytes32 operator)"));
+            data.extend_from_slice(&consul);
+            data.extend_from_slice(&operator);
+            self.hash_typed_data(keccak256(&data[..]))
+        }
+
+        /// EIP-712 hash of `ScoreUpdate(bytes32 consul,uint256 score,uint256 nonce)`
+        #[ink(message)]
+        pub fn hash_consul_score(&self, consul: [u8;32], score: u64, nonce: u64) -> [u8;32] {
+            let mut data: Vec<u8> = Vec::new();
+            data.extend_from_slice(&keccak256(b"ScoreUpdate(bytes32 consul,uint256 score,uint256 nonce)"));
+            data.extend_from_slice(&consul);
+            data.extend_from_slice(&Self::uint256(u128::from(score)));
+            data.extend_from_slice(&Self::uint256(u128::from(nonce)));
+            self.hash_typed_data(keccak256(&data[..]))
         }
 
+        #[ink(message)]
+        pub fn get_score_nonce(&self) -> u64 {
+            self.score_nonce
+        }
+
+        #[ink(message)]
+        pub fn get_consul_info(&self, consul: [u8;32]) -> Option<ConsulInfo> {
+            self.consul_infos.get(&consul).cloned()
+        }
+
+        /// registry entries of the consuls active at the current block, in the order of
+        /// `get_consuls`, `None` for consuls that did not register
+        #[ink(message)]
+        pub fn get_consuls_info(&mut self) -> Vec<Option<ConsulInfo>> {
+            self.get_consuls()
+                .iter()
+                .map(|consul| self.get_consul_info(*consul))
+                .collect()
+        }
+
+        /// threshold of the round active at the current block, like `get_consuls`,
+        /// `None` if no round is active
+        #[ink(message)]
+        pub fn get_threshold(&self) -> Option<Threshold> {
+            self.get_active_round()
+                .and_then(|round_id| self.get_threshold_by_round_id(round_id))
+        }
+
+        #[ink(message)]
+        pub fn get_threshold_by_round_id(&self, round_id: u64) -> Option<Threshold> {
+            self.thresholds_by_rounds.get(&round_id).cloned()
+        }
+
+        /// number of signatures the active consuls need to authorize a change,
+        /// zero if no round is active
+        #[ink(message)]
+        pub fn get_benefitial_value(&mut self) -> u128 {
+            match self.get_active_round() {
+                Some(round_id) => {
+                    let consuls_count = self.get_consuls_by_round_id(round_id).len();
+                    self.get_threshold_by_round_id(round_id)
+                        .map_or(0, |threshold| threshold.required_signatures(consuls_count))
+                }
+                None => 0,
+            }
+        }
+
+        #[ink(message)]
+        pub fn get_key_types(&self) -> Vec<KeyType> {
+            self.get_active_round()
+                .map(|round_id| self.get_key_types_by_round_id(round_id))
+                .unwrap_or_default()
+        }
+
+        #[ink(message)]
+        pub fn get_key_types_by_round_id(&self, round_id: u64) -> Vec<KeyType> {
+            self.key_types_by_rounds
+                .get(&round_id)
+                .unwrap_or(&Vec::new())
+                .clone()
+        }
+
+        #[ink(message)]
+        pub fn get_round_schedule(&self, round_id: u64) -> Option<RoundSchedule> {
+            self.schedules_by_rounds.get(&round_id).cloned()
+        }
+
+        /// The latest round activated at `block`, if it has not expired yet.
+        /// An expired round does not fall back to older rounds, they were replaced by it.
+        #[ink(message)]
+        pub fn get_round_at_block(&self, block: BlockNumber) -> Option<u64> {
+            // activation blocks never decrease along `rounds`, so only the schedules
+            // the binary search visits are read
+            let activated = self.rounds_partition_point(|round_id| {
+                self.get_round_schedule(round_id)
+                    .map_or(false, |schedule| schedule.activation_block <= block)
+            });
+            if activated == 0 { return None; }
+            let round_id = self.rounds[activated - 1];
+            let schedule = self.get_round_schedule(round_id)?;
+            if schedule.is_active_at(block) { Some(round_id) } else { None }
+        }
+
+        #[ink(message)]
+        pub fn get_active_round(&self) -> Option<u64> {
+            self.get_round_at_block(self.env().block_number())
+        }
+
+        /// consuls active at the current block, empty if the last active round expired
         #[ink(message)]
         pub fn get_consuls(&mut self) -> Vec<[u8;32]> {
-            self.get_consuls_by_round_id(self.last_round)
+            let block = self.env().block_number();
+            self.get_consuls_at_block(block)
+        }
+
+        #[ink(message)]
+        pub fn get_consuls_at_block(&mut self, block: BlockNumber) -> Vec<[u8;32]> {
+            match self.get_round_at_block(block) {
+                Some(round_id) => self.get_consuls_by_round_id(round_id),
+                None => Vec::new(),
+            }
         }
         
         #[ink(message)]
-        pub fn get_consuls_by_round_id(&mut self, round_id: [u8;32]) -> Vec<[u8;32]> {
+        pub fn get_consuls_by_round_id(&mut self, round_id: u64) -> Vec<[u8;32]> {
             self.consuls_by_rounds
                 .get(&round_id)
                 .unwrap_or(&Vec::new())
                 .clone()
         }
+
+        #[ink(message)]
+        pub fn get_last_round(&self) -> u64 {
+            self.last_round
+        }
+
+        /// up to `limit` known round ids starting from `from` inclusive, in ascending order
+        #[ink(message)]
+        pub fn get_rounds(&self, from: u64, limit: u32) -> Vec<u64> {
+            let start = self.rounds_partition_point(|round_id| round_id < from);
+            (start..self.rounds.len())
+                .take(limit as usize)
+                .map(|i| self.rounds[i])
+                .collect()
+        }
+
+        /// number of leading `rounds` for which `pred` holds, `pred` has to hold for a prefix of them
+        fn rounds_partition_point<F: Fn(u64) -> bool>(&self, pred: F) -> u32 {
+            let (mut low, mut high) = (0, self.rounds.len());
+            while low < high {
+                let mid = low + (high - low) / 2;
+                if pred(self.rounds[mid]) {
+                    low = mid + 1;
+                } else {
+                    high = mid;
+                }
+            }
+            low
+        }
     }
     #[cfg(test)]
     mod tests {
         use super::*;
+        use ink_lang as ink;
+        use web3::signing::{Key, SecretKey, SecretKeyRef};
+
+        const CHAIN_ID: u64 = 42;
+
+        fn secret(seed: u8) -> SecretKey {
+            SecretKey::from_slice(&[seed;32]).unwrap()
+        }
+
+        /// consul entry of the key, the address takes the first 20 bytes
+        fn consul(seed: u8) -> [u8;32] {
+            let mut consul = [0u8;32];
+            consul[0..20].copy_from_slice(SecretKeyRef::new(&secret(seed)).address().as_bytes());
+            consul
+        }
+
+        /// bitmap of the given consul positions
+        fn bitmap(consuls_count: usize, positions: &[usize]) -> Vec<u8> {
+            let mut signers = vec![0u8; (consuls_count + 7) / 8];
+            for &i in positions {
+                signers[i / 8] |= 1 << (i % 8);
+            }
+            signers
+        }
+
+        /// signs `hash` with the key at each position, `None` marks a consul that did not sign
+        fn sign(hash: [u8;32], signers: &[Option<u8>]) -> (Vec<u8>, Vec<Signature>) {
+            let mut positions = Vec::new();
+            let mut signatures = Vec::new();
+            for (i, signer) in signers.iter().enumerate() {
+                if let Some(seed) = signer {
+                    let sig = SecretKeyRef::new(&secret(*seed)).sign(&hash, None).unwrap();
+                    positions.push(i);
+                    signatures.push(Signature { v: sig.v, r: sig.r.into(), s: sig.s.into() });
+                }
+            }
+            (bitmap(signers.len(), &positions), signatures)
+        }
+
+        #[ink::test]
+        fn current_consuls_rotate_the_set() {
+            let mut gravity = Gravity::new(vec![consul(1), consul(2), consul(3)], 2, CHAIN_ID);
+            let new_consuls = vec![consul(4), consul(5), consul(6)];
+            let round_id = 1;
+            let hash = gravity.hash_new_consuls(new_consuls.clone(), round_id, 0, None, 0);
+            let (signers, signatures) = sign(hash, &[Some(1), None, Some(3)]);
+            assert_eq!(gravity.update_consuls(new_consuls.clone(), signers, signatures, round_id, 0, None, 0), Ok(()));
+            assert_eq!(gravity.get_consuls(), new_consuls);
+            assert_eq!(gravity.get_last_round(), 1);
+            assert_eq!(gravity.get_rounds(0, 10), vec![0, 1]);
+        }
+
+        #[ink::test]
+        fn rotation_signed_for_another_chain_is_rejected() {
+            let consuls = vec![consul(1), consul(2), consul(3)];
+            let other_chain = Gravity::new(consuls.clone(), 2, CHAIN_ID + 1);
+            let mut gravity = Gravity::new(consuls.clone(), 2, CHAIN_ID);
+            assert_ne!(other_chain.domain_separator(), gravity.domain_separator());
+
+            let new_consuls = vec![consul(4), consul(5), consul(6)];
+            let hash = other_chain.hash_new_consuls(new_consuls.clone(), 1, 0, None, 0);
+            let (signers, signatures) = sign(hash, &[Some(1), Some(2), None]);
+            assert_eq!(gravity.update_consuls(new_consuls, signers, signatures, 1, 0, None, 0), Err(Error::InvalidSignature));
+            assert_eq!(gravity.get_consuls(), consuls);
+        }
+
+        #[ink::test]
+        fn same_round_can_not_be_replayed() {
+            let mut gravity = Gravity::new(vec![consul(1), consul(2), consul(3)], 2, CHAIN_ID);
+            let new_consuls = vec![consul(1), consul(2), consul(4)];
+            let hash = gravity.hash_new_consuls(new_consuls.clone(), 1, 0, None, 0);
+            let (signers, signatures) = sign(hash, &[Some(1), Some(2), None]);
+            assert_eq!(gravity.update_consuls(new_consuls.clone(), signers.clone(), signatures.clone(), 1, 0, None, 0), Ok(()));
+            assert_eq!(gravity.update_consuls(new_consuls, signers, signatures, 1, 0, None, 0), Err(Error::StaleRound));
+            assert_eq!(gravity.get_rounds(1, 10), vec![1]);
+        }
+
+        type Event = <Gravity as ::ink_lang::BaseEvent>::Type;
+
+        fn recorded_events() -> Vec<Event> {
+            ink_env::test::recorded_events()
+                .map(|event| <Event as scale::Decode>::decode(&mut &event.data[..]).expect("Invalid event"))
+                .collect()
+        }
+
+        fn advance_blocks(count: u32) {
+            for _ in 0..count {
+                ink_env::test::advance_block::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot advance block");
+            }
+        }
+
+        #[ink::test]
+        fn scheduled_round_activates_and_expires() {
+            let consuls = vec![consul(1), consul(2), consul(3)];
+            let mut gravity = Gravity::new(consuls.clone(), 2, CHAIN_ID);
+            let new_consuls = vec![consul(4), consul(5), consul(6)];
+            let hash = gravity.hash_new_consuls(new_consuls.clone(), 1, 2, Some(4), 2);
+            let (signers, signatures) = sign(hash, &[Some(1), Some(2), None]);
+            assert_eq!(gravity.update_consuls(new_consuls.clone(), signers, signatures, 1, 2, Some(4), 2), Ok(()));
+            assert_eq!(gravity.get_consuls(), consuls);
+
+            advance_blocks(2);
+            assert_eq!(gravity.get_consuls(), new_consuls);
+            assert_eq!(gravity.get_consuls_at_block(1), consuls);
+            assert_eq!(gravity.get_active_round(), Some(1));
+
+            advance_blocks(2);
+            assert_eq!(gravity.get_consuls(), Vec::<[u8;32]>::new());
+            assert_eq!(gravity.get_consuls_at_block(3), new_consuls);
+
+            // the expired quorum can not change the threshold
+            let threshold = Threshold::Absolute(3);
+            let hash = gravity.hash_new_threshold(threshold, 2);
+            let (signers, signatures) = sign(hash, &[Some(4), Some(5), None]);
+            assert_eq!(gravity.update_threshold(threshold, signers, signatures, 2), Err(Error::NoActiveRound));
+
+            // but during its recovery blocks it can still rotate to a set that takes over right away
+            let newer_consuls = vec![consul(7), consul(8), consul(9)];
+            let hash = gravity.hash_new_consuls(newer_consuls.clone(), 2, 5, None, 0);
+            let (signers, signatures) = sign(hash, &[Some(4), Some(5), None]);
+            assert_eq!(
+                gravity.update_consuls(newer_consuls.clone(), signers, signatures, 2, 5, None, 0),
+                Err(Error::InvalidSchedule)
+            );
+            let hash = gravity.hash_new_consuls(newer_consuls.clone(), 2, 4, None, 0);
+            let (signers, signatures) = sign(hash, &[Some(4), Some(5), None]);
+            assert_eq!(gravity.update_consuls(newer_consuls.clone(), signers, signatures, 2, 4, None, 0), Ok(()));
+            assert_eq!(gravity.get_consuls(), newer_consuls);
+            assert_eq!(gravity.get_threshold(), Some(Threshold::Absolute(2)));
+        }
+
+        #[ink::test]
+        fn lapsed_round_can_not_rotate_after_recovery() {
+            let mut gravity = Gravity::new(vec![consul(1), consul(2), consul(3)], 2, CHAIN_ID);
+            let new_consuls = vec![consul(4), consul(5), consul(6)];
+            let hash = gravity.hash_new_consuls(new_consuls.clone(), 1, 0, Some(2), 2);
+            let (signers, signatures) = sign(hash, &[Some(1), Some(2), None]);
+            assert_eq!(gravity.update_consuls(new_consuls, signers, signatures, 1, 0, Some(2), 2), Ok(()));
+
+            advance_blocks(4);
+            let newer_consuls = vec![consul(7), consul(8), consul(9)];
+            let hash = gravity.hash_new_consuls(newer_consuls.clone(), 2, 4, None, 0);
+            let (signers, signatures) = sign(hash, &[Some(4), Some(5), None]);
+            assert_eq!(
+                gravity.update_consuls(newer_consuls, signers, signatures, 2, 4, None, 0),
+                Err(Error::NoActiveRound)
+            );
+            assert_eq!(gravity.get_last_round(), 1);
+        }
+
+        #[ink::test]
+        fn recovery_blocks_are_part_of_the_signed_rotation() {
+            let mut gravity = Gravity::new(vec![consul(1), consul(2), consul(3)], 2, CHAIN_ID);
+            let new_consuls = vec![consul(4), consul(5), consul(6)];
+            let hash = gravity.hash_new_consuls(new_consuls.clone(), 1, 0, Some(2), 2);
+            let (signers, signatures) = sign(hash, &[Some(1), Some(2), None]);
+            assert_eq!(
+                gravity.update_consuls(new_consuls, signers, signatures, 1, 0, Some(2), 100),
+                Err(Error::InvalidSignature)
+            );
+        }
+
+        #[ink::test]
+        fn round_can_not_leave_a_gap_after_expiry() {
+            let mut gravity = Gravity::new(vec![consul(1), consul(2), consul(3)], 2, CHAIN_ID);
+            let new_consuls = vec![consul(4), consul(5), consul(6)];
+            let hash = gravity.hash_new_consuls(new_consuls.clone(), 1, 0, Some(5), 0);
+            let (signers, signatures) = sign(hash, &[Some(1), Some(2), None]);
+            assert_eq!(gravity.update_consuls(new_consuls, signers, signatures, 1, 0, Some(5), 0), Ok(()));
+
+            let newer_consuls = vec![consul(7), consul(8), consul(9)];
+            let hash = gravity.hash_new_consuls(newer_consuls.clone(), 2, 6, None, 0);
+            let (signers, signatures) = sign(hash, &[Some(4), Some(5), None]);
+            assert_eq!(
+                gravity.update_consuls(newer_consuls.clone(), signers, signatures, 2, 6, None, 0),
+                Err(Error::InvalidSchedule)
+            );
+            let hash = gravity.hash_new_consuls(newer_consuls.clone(), 2, 5, None, 0);
+            let (signers, signatures) = sign(hash, &[Some(4), Some(5), None]);
+            assert_eq!(gravity.update_consuls(newer_consuls, signers, signatures, 2, 5, None, 0), Ok(()));
+            assert_eq!(gravity.get_round_at_block(4), Some(1));
+            assert_eq!(gravity.get_round_at_block(5), Some(2));
+        }
+
+        #[ink::test]
+        fn round_can_not_activate_before_last_round() {
+            let mut gravity = Gravity::new(vec![consul(1), consul(2), consul(3)], 2, CHAIN_ID);
+            let new_consuls = vec![consul(4), consul(5), consul(6)];
+            let hash = gravity.hash_new_consuls(new_consuls.clone(), 1, 5, None, 0);
+            let (signers, signatures) = sign(hash, &[Some(1), Some(2), None]);
+            assert_eq!(gravity.update_consuls(new_consuls, signers, signatures, 1, 5, None, 0), Ok(()));
+
+            let newer_consuls = vec![consul(7), consul(8), consul(9)];
+            let hash = gravity.hash_new_consuls(newer_consuls.clone(), 2, 3, None, 0);
+            let (signers, signatures) = sign(hash, &[Some(1), Some(2), None]);
+            assert_eq!(gravity.update_consuls(newer_consuls, signers, signatures, 2, 3, None, 0), Err(Error::InvalidSchedule));
+        }
+
+        #[ink::test]
+        fn threshold_change_keeps_history() {
+            let consuls = vec![consul(1), consul(2), consul(3)];
+            let mut gravity = Gravity::new(consuls.clone(), 1, CHAIN_ID);
+            let threshold = Threshold::Fraction { numerator: 2, denominator: 3 };
+            let hash = gravity.hash_new_threshold(threshold, 1);
+            let (signers, signatures) = sign(hash, &[Some(1), None, None]);
+            let start = recorded_events().len();
+            assert_eq!(gravity.update_threshold(threshold, signers, signatures, 1), Ok(()));
+            let events = recorded_events();
+            assert_eq!(events.len(), start + 2);
+            match &events[start] {
+                Event::ConsulsUpdated(event) => {
+                    assert_eq!(event.round_id, 1);
+                    assert_eq!(event.consuls, consuls);
+                }
+                _ => panic!("expected a ConsulsUpdated event"),
+            }
+            match &events[start + 1] {
+                Event::ThresholdUpdated(event) => assert_eq!(event.threshold, threshold),
+                _ => panic!("expected a ThresholdUpdated event"),
+            }
+            assert_eq!(gravity.get_threshold(), Some(threshold));
+            assert_eq!(gravity.get_benefitial_value(), 2);
+            assert_eq!(gravity.get_threshold_by_round_id(0), Some(Threshold::Absolute(1)));
+            assert_eq!(gravity.get_consuls(), consuls);
+
+            // one signature is no longer enough to rotate
+            let new_consuls = vec![consul(4), consul(5), consul(6)];
+            let hash = gravity.hash_new_consuls(new_consuls.clone(), 2, 0, None, 0);
+            let (signers, signatures) = sign(hash, &[Some(1), None, None]);
+            assert_eq!(
+                gravity.update_consuls(new_consuls, signers, signatures, 2, 0, None, 0),
+                Err(Error::NotEnoughSignatures)
+            );
+        }
+
+        fn ed25519_keypair(seed: u8) -> ed25519_dalek::Keypair {
+            let secret = ed25519_dalek::SecretKey::from_bytes(&[seed;32]).unwrap();
+            let public = ed25519_dalek::PublicKey::from(&secret);
+            ed25519_dalek::Keypair { secret, public }
+        }
+
+        fn sr25519_keypair(seed: u8) -> schnorrkel::Keypair {
+            schnorrkel::MiniSecretKey::from_bytes(&[seed;32])
+                .unwrap()
+                .expand_to_keypair(schnorrkel::ExpansionMode::Ed25519)
+        }
+
+        fn split_signature(sig: [u8;64]) -> ([u8;32], [u8;32]) {
+            let (mut r, mut s) = ([0u8;32], [0u8;32]);
+            r.copy_from_slice(&sig[0..32]);
+            s.copy_from_slice(&sig[32..64]);
+            (r, s)
+        }
+
+        #[ink::test]
+        fn mixed_key_types_rotate_the_set() {
+            use ed25519_dalek::Signer;
+
+            let ed = ed25519_keypair(2);
+            let sr = sr25519_keypair(3);
+            let consuls = vec![consul(1), ed.public.to_bytes(), sr.public.to_bytes()];
+            let key_types = vec![KeyType::Secp256k1, KeyType::Ed25519, KeyType::Sr25519];
+            let mut gravity = Gravity::new_with_key_types(consuls, key_types, 3, CHAIN_ID);
+
+            let new_consuls = vec![consul(4), consul(5), consul(6)];
+            let hash = gravity.hash_new_consuls(new_consuls.clone(), 1, 0, None, 0);
+            let (_, mut signatures) = sign(hash, &[Some(1), None, None]);
+            let (r, s) = split_signature(ed.sign(&hash).to_bytes());
+            signatures.push(Signature { v: 0, r, s });
+            let (r, s) = split_signature(sr.sign_simple(SR25519_SIGNING_CONTEXT, &hash).to_bytes());
+            signatures.push(Signature { v: 0, r, s });
+            let signers = bitmap(3, &[0, 1, 2]);
+            assert_eq!(gravity.update_consuls(new_consuls.clone(), signers.clone(), signatures.clone(), 1, 0, None, 0), Ok(()));
+            assert_eq!(gravity.get_consuls(), new_consuls);
+            assert_eq!(gravity.get_key_types(), vec![KeyType::Secp256k1; 3]);
+
+            // a signature of another ed25519 key is rejected
+            let mut gravity = Gravity::new_with_key_types(
+                vec![consul(1), ed.public.to_bytes(), sr.public.to_bytes()],
+                vec![KeyType::Secp256k1, KeyType::Ed25519, KeyType::Sr25519],
+                3,
+                CHAIN_ID,
+            );
+            let (r, s) = split_signature(ed25519_keypair(7).sign(&hash).to_bytes());
+            signatures[1] = Signature { v: 0, r, s };
+            assert_eq!(gravity.update_consuls(new_consuls, signers, signatures, 1, 0, None, 0), Err(Error::InvalidSignature));
+        }
+
+        #[ink::test]
+        fn mixed_key_types_verify_any_hash() {
+            use ed25519_dalek::Signer;
+
+            let ed = ed25519_keypair(2);
+            let sr = sr25519_keypair(3);
+            let consuls = vec![consul(1), ed.public.to_bytes(), sr.public.to_bytes()];
+            let key_types = vec![KeyType::Secp256k1, KeyType::Ed25519, KeyType::Sr25519];
+            let gravity = Gravity::new_with_key_types(consuls, key_types, 2, CHAIN_ID);
+
+            let hash = keccak256(b"change of another contract");
+            let (r, s) = split_signature(ed.sign(&hash).to_bytes());
+            let ed_signature = Signature { v: 0, r, s };
+            let (r, s) = split_signature(sr.sign_simple(SR25519_SIGNING_CONTEXT, &hash).to_bytes());
+            let sr_signature = Signature { v: 0, r, s };
+            assert_eq!(gravity.verify_consuls(hash, bitmap(3, &[1, 2]), vec![ed_signature, sr_signature]), Ok(()));
+            assert_eq!(gravity.verify_consuls(hash, bitmap(3, &[1]), vec![ed_signature]), Err(Error::NotEnoughSignatures));
+            assert_eq!(
+                gravity.verify_consuls(keccak256(b"other"), bitmap(3, &[1, 2]), vec![ed_signature, sr_signature]),
+                Err(Error::InvalidSignature)
+            );
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "the threshold has to be reachable by the consuls")]
+        fn zero_initial_threshold_is_rejected() {
+            Gravity::new(vec![consul(1), consul(2), consul(3)], 0, CHAIN_ID);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "the threshold has to be reachable by the consuls")]
+        fn initial_threshold_above_consul_count_is_rejected() {
+            Gravity::new(vec![consul(1), consul(2), consul(3)], 4, CHAIN_ID);
+        }
+
+        #[ink::test]
+        fn unreachable_threshold_is_rejected() {
+            let mut gravity = Gravity::new(vec![consul(1), consul(2), consul(3)], 2, CHAIN_ID);
+            let threshold = Threshold::Absolute(4);
+            let hash = gravity.hash_new_threshold(threshold, 1);
+            let (signers, signatures) = sign(hash, &[Some(1), Some(2), Some(3)]);
+            assert_eq!(gravity.update_threshold(threshold, signers, signatures, 1), Err(Error::InvalidThreshold));
+        }
+
+        #[ink::test]
+        fn self_approved_set_is_rejected() {
+            let consuls = vec![consul(1), consul(2), consul(3)];
+            let mut gravity = Gravity::new(consuls.clone(), 2, CHAIN_ID);
+            let new_consuls = vec![consul(4), consul(5), consul(6)];
+            let round_id = 1;
+            let hash = gravity.hash_new_consuls(new_consuls.clone(), round_id, 0, None, 0);
+            let (signers, signatures) = sign(hash, &[Some(4), Some(5), Some(6)]);
+            assert_eq!(gravity.update_consuls(new_consuls, signers, signatures, round_id, 0, None, 0), Err(Error::InvalidSignature));
+            assert_eq!(gravity.get_consuls(), consuls);
+        }
+
+        #[ink::test]
+        fn not_enough_signatures_are_rejected() {
+            let consuls = vec![consul(1), consul(2), consul(3)];
+            let mut gravity = Gravity::new(consuls.clone(), 2, CHAIN_ID);
+            let new_consuls = vec![consul(4), consul(5), consul(6)];
+            let round_id = 1;
+            let hash = gravity.hash_new_consuls(new_consuls.clone(), round_id, 0, None, 0);
+            let (signers, signatures) = sign(hash, &[Some(1), None, None]);
+            assert_eq!(gravity.update_consuls(new_consuls, signers, signatures, round_id, 0, None, 0), Err(Error::NotEnoughSignatures));
+            assert_eq!(gravity.get_consuls(), consuls);
+        }
+
+        #[ink::test]
+        fn duplicate_consul_is_counted_once() {
+            let consuls = vec![consul(1), consul(1), consul(2)];
+            let mut gravity = Gravity::new(consuls.clone(), 2, CHAIN_ID);
+            let new_consuls = vec![consul(4), consul(5), consul(6)];
+            let round_id = 1;
+            let hash = gravity.hash_new_consuls(new_consuls.clone(), round_id, 0, None, 0);
+            let (signers, signatures) = sign(hash, &[Some(1), Some(1), None]);
+            assert_eq!(gravity.update_consuls(new_consuls, signers, signatures, round_id, 0, None, 0), Err(Error::NotEnoughSignatures));
+            assert_eq!(gravity.get_consuls(), consuls);
+        }
+
+        #[ink::test]
+        fn signatures_out_of_position_are_rejected() {
+            let consuls = vec![consul(1), consul(2), consul(3)];
+            let mut gravity = Gravity::new(consuls.clone(), 2, CHAIN_ID);
+            let new_consuls = vec![consul(4), consul(5), consul(6)];
+            let round_id = 1;
+            let hash = gravity.hash_new_consuls(new_consuls.clone(), round_id, 0, None, 0);
+            let (signers, signatures) = sign(hash, &[Some(2), Some(1), None]);
+            assert_eq!(gravity.update_consuls(new_consuls, signers, signatures, round_id, 0, None, 0), Err(Error::InvalidSignature));
+            assert_eq!(gravity.get_consuls(), consuls);
+        }
+
+        #[ink::test]
+        fn mismatched_signature_lengths_are_rejected() {
+            let consuls = vec![consul(1), consul(2), consul(3)];
+            let mut gravity = Gravity::new(consuls.clone(), 2, CHAIN_ID);
+            let new_consuls = vec![consul(4), consul(5), consul(6)];
+            let round_id = 1;
+            let hash = gravity.hash_new_consuls(new_consuls.clone(), round_id, 0, None, 0);
+            let (signers, mut signatures) = sign(hash, &[Some(1), Some(2), None]);
+
+            // a bitmap longer than the consul set
+            let mut long_signers = signers.clone();
+            long_signers.push(0);
+            assert_eq!(
+                gravity.update_consuls(new_consuls.clone(), long_signers, signatures.clone(), round_id, 0, None, 0),
+                Err(Error::LengthMismatch)
+            );
+
+            // a bit set past the last consul
+            let extra_signers = vec![signers[0] | 0b1000];
+            assert_eq!(
+                gravity.update_consuls(new_consuls.clone(), extra_signers, signatures.clone(), round_id, 0, None, 0),
+                Err(Error::LengthMismatch)
+            );
+
+            // fewer signatures than set bits
+            signatures.pop();
+            assert_eq!(gravity.update_consuls(new_consuls, signers, signatures, round_id, 0, None, 0), Err(Error::LengthMismatch));
+            assert_eq!(gravity.get_consuls(), consuls);
+        }
+
+        #[ink::test]
+        fn verification_stops_at_threshold() {
+            let mut gravity = Gravity::new(vec![consul(1), consul(2), consul(3)], 2, CHAIN_ID);
+            let new_consuls = vec![consul(4), consul(5), consul(6)];
+            let round_id = 1;
+            let hash = gravity.hash_new_consuls(new_consuls.clone(), round_id, 0, None, 0);
+            let (signers, mut signatures) = sign(hash, &[Some(1), Some(2), Some(3)]);
+            // the threshold is met before the last signature is looked at
+            signatures[2] = Signature { v: 0, r: [0u8;32], s: [0u8;32] };
+            assert_eq!(gravity.update_consuls(new_consuls.clone(), signers, signatures, round_id, 0, None, 0), Ok(()));
+            assert_eq!(gravity.get_consuls(), new_consuls);
+        }
+
+        #[ink::test]
+        fn consul_registers_with_its_own_key() {
+            let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                .expect("Cannot get accounts");
+            let mut gravity = Gravity::new(vec![consul(1), consul(2), consul(3)], 2, CHAIN_ID);
+
+            // a key outside the set and a registration signed by another key are rejected
+            let hash = gravity.hash_consul_registration(consul(4), accounts.alice);
+            // `register_consul` reverts on errors, which the off-chain environment can not
+            // do, so the failures are checked on the function it wraps
+            let (_, signatures) = sign(hash, &[Some(4)]);
+            assert_eq!(gravity.try_register_consul(consul(4), signatures[0]), Err(Error::NotAConsul));
+            let hash = gravity.hash_consul_registration(consul(1), accounts.alice);
+            let (_, signatures) = sign(hash, &[Some(2)]);
+            assert_eq!(gravity.try_register_consul(consul(1), signatures[0]), Err(Error::InvalidSignature));
+
+            let (_, signatures) = sign(hash, &[Some(1)]);
+            assert_eq!(gravity.register_consul(consul(1), signatures[0]), Ok(()));
+            assert_eq!(gravity.try_register_consul(consul(1), signatures[0]), Err(Error::AlreadyRegistered));
+            let info = gravity.get_consul_info(consul(1)).unwrap();
+            assert_eq!(info.operator, accounts.alice);
+            assert_eq!(info.join_round, 0);
+            assert_eq!(gravity.get_consuls_info(), vec![Some(info), None, None]);
+
+            // an active consul can not leave, and the entry moves with its operator
+            assert_eq!(gravity.unregister_consul(consul(1)), Err(Error::ConsulActive));
+            assert_eq!(gravity.set_operator(consul(1), accounts.bob), Ok(()));
+            assert_eq!(gravity.get_consul_info(consul(1)).unwrap().operator, accounts.bob);
+            assert_eq!(gravity.set_operator(consul(1), accounts.alice), Err(Error::NotOperator));
+            assert_eq!(gravity.try_bond(consul(1)), Err(Error::NotOperator));
+            assert_eq!(gravity.try_bond(consul(4)), Err(Error::NotRegistered));
+        }
+
+        #[ink::test]
+        fn consul_can_not_unregister_while_scheduled() {
+            let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                .expect("Cannot get accounts");
+            let mut gravity = Gravity::new(vec![consul(1), consul(2), consul(3)], 2, CHAIN_ID);
+            let hash = gravity.hash_consul_registration(consul(1), accounts.alice);
+            let (_, signatures) = sign(hash, &[Some(1)]);
+            assert_eq!(gravity.register_consul(consul(1), signatures[0]), Ok(()));
+
+            let new_consuls = vec![consul(4), consul(5), consul(6)];
+            let hash = gravity.hash_new_consuls(new_consuls.clone(), 1, 2, None, 0);
+            let (signers, signatures) = sign(hash, &[Some(1), Some(2), None]);
+            assert_eq!(gravity.update_consuls(new_consuls, signers, signatures, 1, 2, None, 0), Ok(()));
+            let newer_consuls = vec![consul(1), consul(5), consul(6)];
+            let hash = gravity.hash_new_consuls(newer_consuls.clone(), 2, 4, None, 0);
+            let (signers, signatures) = sign(hash, &[Some(1), Some(2), None]);
+            assert_eq!(gravity.update_consuls(newer_consuls, signers, signatures, 2, 4, None, 0), Ok(()));
+
+            // consul 1 left the active set but is coming back in round 2
+            advance_blocks(2);
+            assert!(!gravity.get_consuls().contains(&consul(1)));
+            assert_eq!(gravity.unregister_consul(consul(1)), Err(Error::ConsulActive));
+        }
+
+        #[ink::test]
+        fn consul_unregisters_after_leaving() {
+            let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                .expect("Cannot get accounts");
+            let mut gravity = Gravity::new(vec![consul(1), consul(2), consul(3)], 2, CHAIN_ID);
+            let hash = gravity.hash_consul_registration(consul(1), accounts.alice);
+            let (_, signatures) = sign(hash, &[Some(1)]);
+            assert_eq!(gravity.register_consul(consul(1), signatures[0]), Ok(()));
+
+            let new_consuls = vec![consul(4), consul(5), consul(6)];
+            let hash = gravity.hash_new_consuls(new_consuls.clone(), 1, 0, None, 0);
+            let (signers, signatures) = sign(hash, &[Some(1), Some(2), None]);
+            assert_eq!(gravity.update_consuls(new_consuls, signers, signatures, 1, 0, None, 0), Ok(()));
+            assert_eq!(gravity.unregister_consul(consul(1)), Ok(()));
+            assert_eq!(gravity.get_consul_info(consul(1)), None);
+            assert_eq!(gravity.unregister_consul(consul(1)), Err(Error::NotRegistered));
+        }
+
+        #[ink::test]
+        fn consuls_update_score_once() {
+            let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                .expect("Cannot get accounts");
+            let mut gravity = Gravity::new(vec![consul(1), consul(2), consul(3)], 2, CHAIN_ID);
+            let hash = gravity.hash_consul_registration(consul(2), accounts.alice);
+            let (_, signatures) = sign(hash, &[Some(2)]);
+            assert_eq!(gravity.register_consul(consul(2), signatures[0]), Ok(()));
+
+            let hash = gravity.hash_consul_score(consul(2), 7, gravity.get_score_nonce());
+            let (signers, signatures) = sign(hash, &[Some(1), None, Some(3)]);
+            assert_eq!(gravity.update_consul_score(consul(2), 7, signers.clone(), signatures.clone()), Ok(()));
+            assert_eq!(gravity.get_consul_info(consul(2)).unwrap().score, 7);
+            assert_eq!(gravity.get_score_nonce(), 1);
+
+            // the nonce moved on, so the same signatures no longer verify
+            assert_eq!(gravity.update_consul_score(consul(2), 7, signers, signatures), Err(Error::InvalidSignature));
+            let hash = gravity.hash_consul_score(consul(3), 1, gravity.get_score_nonce());
+            let (signers, signatures) = sign(hash, &[Some(1), Some(2), None]);
+            assert_eq!(gravity.update_consul_score(consul(3), 1, signers, signatures), Err(Error::NotRegistered));
+        }
     }
 }
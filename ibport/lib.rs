@@ -133,7 +133,9 @@ mod ibport {
             Ok(())
         }
 
-        #[ink(message)]
+        /// entry point for Nebula subscriptions with `DataType::Bytes`,
+        /// the selector is the one Nebula uses for raw values
+        #[ink(message, selector = "0xA715AC7F")]
         pub fn attach_value(&mut self, data_flow: Vec<u8>) -> Result<()> {
             if self.env().caller() != self.nebula { return Err(Error::NotNebula); }
             let mut i = 0;
@@ -6,10 +6,14 @@ use ink_lang as ink;
 mod nebula {
     use ink_storage::{
         collections::{hashmap::Entry, HashMap},
-        traits::{PackedLayout, SpreadLayout},
+        traits::{pull_spread_root, push_spread_root, PackedLayout, SpreadLayout},
     };
-    use ibport::IBport;
-    use gravity::Gravity;
+    use ink_primitives::Key;
+    use ink_env::{
+        call::{build_call, utils::ReturnType, ExecutionInput, FromAccountId, Selector},
+        ReturnFlags,
+    };
+    use gravity::{Gravity, Signature};
     use std::mem::transmute;
     use web3::types::{Recovery,H160};
     use web3::signing::{ keccak256,recover};
@@ -23,8 +27,28 @@ mod nebula {
         TokenError,
         ConculsReduce,
         SubscriberIdExists,
+        OraclesReduce,
+        PulseExists,
+        SignaturesLengthMismatch,
+        InvalidValueHash,
+        NotEnoughConfirmations,
+        ValueAlreadySent,
+        SubscriberError,
+        InvalidValueType,
     }
     pub type Result<T> = core::result::Result<T, Error>;
+
+    /// selectors of the subscriber entry points, one per `DataType`
+    pub const ATTACH_VALUE_INT_SELECTOR: [u8;4] = [0xF2, 0xBE, 0x51, 0xD1];
+    pub const ATTACH_VALUE_STRING_SELECTOR: [u8;4] = [0xB1, 0x42, 0xE0, 0x8B];
+    pub const ATTACH_VALUE_BYTES_SELECTOR: [u8;4] = [0xA7, 0x15, 0xAC, 0x7F];
+
+    /// name and version of the EIP-712 signing domain
+    const DOMAIN_NAME: &[u8] = b"Nebula";
+    const DOMAIN_VERSION: &[u8] = b"1";
+
+    /// storage root of the contract, see `flush_storage`
+    const ROOT_KEY: [u8;32] = [0x00;32];
    
 
     #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode, SpreadLayout, PackedLayout)]
@@ -46,7 +70,7 @@ mod nebula {
     )]
     struct Pulse {
         data_hash: [u8;32],
-        height: [u8;32],
+        height: BlockNumber,
     }
 
     #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode, SpreadLayout, PackedLayout)]
@@ -65,12 +89,19 @@ mod nebula {
         feature = "std",
         derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
     )]
-    enum DataType {
+    pub enum DataType {
         Int64,
         Str,
         Bytes,
     }
 
+    /// a pulse value decoded according to `DataType`
+    enum TypedValue {
+        Int64(i64),
+        Str(String),
+        Bytes(Vec<u8>),
+    }
+
     #[ink(storage)]
     pub struct Nebula {
         gravity_contract: AccountId,
@@ -82,6 +113,12 @@ mod nebula {
         subscribers_queue: Vec<[u8;32]>,
         pulses_queue: Vec<[u8;32]>,
         subscriptions: HashMap<[u8;32],Subscription>,
+        pulses: HashMap<u64,Pulse>,
+        pulse_ids_by_hash: HashMap<[u8;32],u64>,
+        last_pulse_id: u64,
+        is_pulse_sub_sent: HashMap<(u64,[u8;32]),bool>,
+        /// id of the chain this deployment lives on, part of every signed pulse
+        chain_id: u64,
     }
 
     impl Nebula {
@@ -89,7 +126,8 @@ mod nebula {
         pub fn new(data_type: DataType, 
             gravity_contract: AccountId, 
             benefitial_value: u128,
-            oracles: Vec<[u8;32]>
+            oracles: Vec<[u8;32]>,
+            chain_id: u64,
         ) -> Self {
             Self { 
                 data_type: data_type,
@@ -101,60 +139,281 @@ mod nebula {
                 oracles_queue: Vec::new(),
                 pulses_queue: Vec::new(),
                 subscriptions: HashMap::new(),
+                pulses: HashMap::new(),
+                pulse_ids_by_hash: HashMap::new(),
+                last_pulse_id: 0,
+                is_pulse_sub_sent: HashMap::new(),
+                chain_id: chain_id,
             }
         }
 
+        /// the digest consuls sign to replace the oracles in `round_id`,
+        /// the EIP-712 hash of `UpdateOracles(bytes32 oraclesHash,bytes32 roundId)`
         #[ink(message)]
-        pub fn hash_new_oracles(&self, new_oracles: Vec<[u8;32]>) -> [u8;32] {
-            let mut new_oracles = new_oracles; 
+        pub fn hash_new_oracles(&self, new_oracles: Vec<[u8;32]>, round_id: [u8;32]) -> [u8;32] {
             let mut data: Vec<u8> = Vec::new();
             for i in new_oracles.iter() {
                 data.append(&mut Vec::from(&i[..]));
             }
-            keccak256(&data[..])   
+
+            let mut struct_data: Vec<u8> = Vec::new();
+            struct_data.extend_from_slice(&keccak256(b"UpdateOracles(bytes32 oraclesHash,bytes32 roundId)"));
+            struct_data.extend_from_slice(&keccak256(&data[..]));
+            struct_data.extend_from_slice(&round_id);
+            self.hash_typed_data(keccak256(&struct_data[..]))
         }
 
+        /// Replaces the oracles, signed by the active Gravity consuls under their threshold.
+        /// Gravity checks the signatures, so consuls of every key type it supports can sign.
         pub fn update_oracles(
             &mut self, 
             new_oracles: Vec<[u8;32]>, 
-            v: Vec<u64>, 
-            r: Vec<[u8;32]>, 
-            s: Vec<[u8;32]>,
+            signers: Vec<u8>,
+            signatures: Vec<Signature>,
             round_id: [u8;32],
         ) -> Result<()> {
             if let Entry::Occupied(_) = self.round_mutated.entry(round_id) {
                 return Err(Error::RoundAlreadyMutated);
             } 
 
-            let hash = self.hash_new_oracles(new_oracles.clone());
+            let hash = self.hash_new_oracles(new_oracles.clone(), round_id);
             let gravity_instance = Gravity::from_account_id(self.gravity_contract);
-            let consuls = gravity_instance.get_consuls();
-
-            let mut count: u128 = 0;
-            for i in 0..consuls.len() {
-                let rec = Recovery::new(hash, v[i], r[i].into(), s[i].into())
-                    .as_signature()
-                    .unwrap();
-                let addr = recover(&hash, &rec.0, rec.1).unwrap();
-                let consul_addr = H160::from_slice(&consuls[i][0..20]);
-                if addr == consul_addr { count += 1; }
-            }
-
-            if count < self.benefitial_value { return Err(Error::ConculsReduce); }
+            gravity_instance
+                .verify_consuls(hash, signers, signatures)
+                .map_err(|_| Error::ConculsReduce)?;
             self.oracles = new_oracles;
             self.round_mutated.insert(round_id,true);
             Ok(())
         }
 
+        #[ink(message)]
+        pub fn get_chain_id(&self) -> u64 {
+            self.chain_id
+        }
+
+        /// packs a number as a big-endian uint256
+        fn uint256(value: u128) -> [u8;32] {
+            let mut packed = [0u8;32];
+            packed[16..32].copy_from_slice(&value.to_be_bytes());
+            packed
+        }
+
+        /// EIP-712 domain separator, so a pulse signed for this contract on this chain
+        /// can not be replayed on another Nebula with the same oracles
+        #[ink(message)]
+        pub fn domain_separator(&self) -> [u8;32] {
+            let contract: [u8;32] = unsafe { transmute(self.env().account_id()) };
+            let mut data: Vec<u8> = Vec::new();
+            data.extend_from_slice(&keccak256(b"EIP712Domain(string name,string version,uint256 chainId,bytes32 verifyingContract)"));
+            data.extend_from_slice(&keccak256(DOMAIN_NAME));
+            data.extend_from_slice(&keccak256(DOMAIN_VERSION));
+            data.extend_from_slice(&Self::uint256(u128::from(self.chain_id)));
+            data.extend_from_slice(&contract);
+            keccak256(&data[..])
+        }
+
+        fn hash_typed_data(&self, struct_hash: [u8;32]) -> [u8;32] {
+            let mut data: Vec<u8> = Vec::new();
+            data.extend_from_slice(b"\x19\x01");
+            data.extend_from_slice(&self.domain_separator());
+            data.extend_from_slice(&struct_hash);
+            keccak256(&data[..])
+        }
+
+        /// the digest oracles sign for a pulse, the EIP-712 hash of `Pulse(bytes32 dataHash)`
+        #[ink(message)]
+        pub fn hash_pulse(&self, data_hash: [u8;32]) -> [u8;32] {
+            let mut struct_data: Vec<u8> = Vec::new();
+            struct_data.extend_from_slice(&keccak256(b"Pulse(bytes32 dataHash)"));
+            struct_data.extend_from_slice(&data_hash);
+            self.hash_typed_data(keccak256(&struct_data[..]))
+        }
+
+        /// counts how many distinct oracles signed `hash_pulse(data_hash)`,
+        /// signature `i` is expected to belong to `oracles[i]`
+        fn count_oracle_signatures(
+            &self,
+            data_hash: [u8;32],
+            v: &[u64],
+            r: &[[u8;32]],
+            s: &[[u8;32]],
+        ) -> Result<u128> {
+            let oracles_count = self.oracles.len();
+            if v.len() != oracles_count || r.len() != oracles_count || s.len() != oracles_count {
+                return Err(Error::SignaturesLengthMismatch);
+            }
+
+            let hash = self.hash_pulse(data_hash);
+            let mut signers: Vec<H160> = Vec::new();
+            for i in 0..oracles_count {
+                // oracles that did not sign pass an empty signature, skip them
+                let rec = match Recovery::new(hash, v[i], r[i].into(), s[i].into()).as_signature() {
+                    Some(rec) => rec,
+                    None => continue,
+                };
+                let addr = match recover(&hash, &rec.0, rec.1) {
+                    Ok(addr) => addr,
+                    Err(_) => continue,
+                };
+                let oracle_addr = H160::from_slice(&self.oracles[i][0..20]);
+                // an oracle listed several times is counted once
+                if addr == oracle_addr && !signers.contains(&addr) { signers.push(addr); }
+            }
+            Ok(signers.len() as u128)
+        }
+
+        #[ink(message)]
+        pub fn send_hash_value(
+            &mut self,
+            data_hash: [u8;32],
+            v: Vec<u64>,
+            r: Vec<[u8;32]>,
+            s: Vec<[u8;32]>,
+        ) -> Result<u64> {
+            if let Entry::Occupied(_) = self.pulse_ids_by_hash.entry(data_hash) {
+                return Err(Error::PulseExists);
+            }
+
+            let count = self.count_oracle_signatures(data_hash, &v, &r, &s)?;
+            if count < self.benefitial_value { return Err(Error::OraclesReduce); }
+
+            let pulse_id = self.last_pulse_id + 1;
+            self.pulses.insert(pulse_id, Pulse{
+                data_hash: data_hash,
+                height: self.env().block_number(),
+            });
+            self.pulse_ids_by_hash.insert(data_hash, pulse_id);
+            self.last_pulse_id = pulse_id;
+            Ok(pulse_id)
+        }
+
+        #[ink(message)]
+        pub fn get_last_pulse_id(&self) -> u64 {
+            self.last_pulse_id
+        }
+
+        #[ink(message)]
+        pub fn get_pulse_hash(&self, pulse_id: u64) -> Option<[u8;32]> {
+            self.pulses
+                .get(&pulse_id)
+                .map(|pulse| pulse.data_hash)
+        }
+
+        #[ink(message)]
+        pub fn send_value_to_subs(
+            &mut self,
+            value: Vec<u8>,
+            pulse_id: u64,
+            sub_id: [u8;32],
+        ) -> Result<()> {
+            let pulse = self.pulses.get(&pulse_id).ok_or(Error::NotFound)?;
+            if keccak256(&value[..]) != pulse.data_hash {
+                return Err(Error::InvalidValueHash);
+            }
+            let pulse_height = pulse.height;
+
+            let sub = self.subscriptions.get(&sub_id).ok_or(Error::NotFound)?;
+            let contact_address = sub.contact_address;
+            let confirmations = self.env().block_number().saturating_sub(pulse_height);
+            if (confirmations as u64) < sub.minimal_comformations {
+                return Err(Error::NotEnoughConfirmations);
+            }
+
+            if self.is_value_sent(pulse_id, sub_id) {
+                return Err(Error::ValueAlreadySent);
+            }
+            let value = self.decode_value(value)?;
+
+            // the value is marked as sent before the call, so a re-entrant call can not send it again
+            self.is_pulse_sub_sent.insert((pulse_id, sub_id), true);
+            self.flush_storage();
+            let res = self.send_typed_value(contact_address, value);
+            self.reload_storage();
+            match res {
+                Ok(()) => Ok(()),
+                Err(err) => Self::revert(err),
+            }
+        }
+
+        /// decodes the value according to `data_type`
+        fn decode_value(&self, value: Vec<u8>) -> Result<TypedValue> {
+            match self.data_type {
+                DataType::Int64 => {
+                    if value.len() != 8 { return Err(Error::InvalidValueType); }
+                    let mut bytes = [0u8;8];
+                    bytes.copy_from_slice(&value[..]);
+                    Ok(TypedValue::Int64(i64::from_be_bytes(bytes)))
+                }
+                DataType::Str => {
+                    let value = String::from_utf8(value).map_err(|_| Error::InvalidValueType)?;
+                    Ok(TypedValue::Str(value))
+                }
+                DataType::Bytes => Ok(TypedValue::Bytes(value)),
+            }
+        }
+
+        /// passes the value to the entry point of the subscriber matching its type
+        fn send_typed_value(&self, contact_address: AccountId, value: TypedValue) -> Result<()> {
+            match value {
+                TypedValue::Int64(value) => self.call_subscriber(contact_address, ATTACH_VALUE_INT_SELECTOR, value),
+                TypedValue::Str(value) => self.call_subscriber(contact_address, ATTACH_VALUE_STRING_SELECTOR, value),
+                TypedValue::Bytes(value) => self.call_subscriber(contact_address, ATTACH_VALUE_BYTES_SELECTOR, value),
+            }
+        }
+
+        /// same as `Token::flush_storage`, writes storage before calling a subscriber
+        fn flush_storage(&self) {
+            push_spread_root::<Self>(self, &Key::from(ROOT_KEY));
+        }
+
+        /// same as `Token::reload_storage`, keeps what a re-entrant call wrote
+        fn reload_storage(&mut self) {
+            *self = pull_spread_root::<Self>(&Key::from(ROOT_KEY));
+        }
+
+        /// same as `Token::revert`, rolls back the sent flag when the subscriber fails
+        fn revert(err: Error) -> ! {
+            ink_env::return_value::<Result<()>>(ReturnFlags::default().set_reverted(true), &Err(err))
+        }
+
+        /// the error of the subscriber is decoded the same way `Token::transfer_and_call`
+        /// decodes the error of its recipient
+        fn call_subscriber<T: scale::Encode>(
+            &self,
+            contact_address: AccountId,
+            selector: [u8;4],
+            value: T,
+        ) -> Result<()> {
+            let res = build_call::<<Self as ::ink_lang::ContractEnv>::Env>()
+                .callee(contact_address)
+                .gas_limit(0)
+                .transferred_value(0)
+                .exec_input(ExecutionInput::new(Selector::new(selector)).push_arg(value))
+                .returns::<ReturnType<core::result::Result<(), u8>>>()
+                .fire();
+            match res {
+                Ok(Ok(())) => Ok(()),
+                _ => Err(Error::SubscriberError),
+            }
+        }
+
+        #[ink(message)]
+        pub fn is_value_sent(&self, pulse_id: u64, sub_id: [u8;32]) -> bool {
+            self.is_pulse_sub_sent
+                .get(&(pulse_id, sub_id))
+                .cloned()
+                .unwrap_or(false)
+        }
+
         fn get_subscriber_id(&self, contact_address: AccountId, minimal_comformations: u64) -> [u8;32] {
             // parse all needed to byte arrays
             let msg_sig: [u8;4] = ink_env::decode_input().unwrap();
             let caller: [u8;32] = unsafe { transmute(self.env().caller()) };
             let contact_address: [u8;32] = unsafe { transmute(contact_address) };
-            let minimal_comformations: [u8;4] = unsafe { transmute(minimal_comformations) };
+            let minimal_comformations: [u8;8] = minimal_comformations.to_be_bytes();
 
             //concat into one byte array
-            let data: Vec<u8> = Vec::new();
+            let mut data: Vec<u8> = Vec::new();
             data.extend_from_slice(&msg_sig[..]);
             data.extend_from_slice(&caller[..]);
             data.extend_from_slice(&contact_address[..]);
@@ -190,9 +449,180 @@ mod nebula {
     #[cfg(test)]
     mod tests {
         use super::*;
+        use ink_lang as ink;
+        use web3::signing::{Key, SecretKey, SecretKeyRef};
+
+        const CHAIN_ID: u64 = 42;
 
         #[test]
         fn default_works() {
         }
+
+        fn secret(seed: u8) -> SecretKey {
+            SecretKey::from_slice(&[seed;32]).unwrap()
+        }
+
+        /// oracle entry of the key, the address takes the first 20 bytes
+        fn oracle(seed: u8) -> [u8;32] {
+            let mut oracle = [0u8;32];
+            oracle[0..20].copy_from_slice(SecretKeyRef::new(&secret(seed)).address().as_bytes());
+            oracle
+        }
+
+        /// signs `hash` with the given keys in order, `None` leaves an empty signature
+        fn sign(hash: [u8;32], signers: &[Option<u8>]) -> (Vec<u64>, Vec<[u8;32]>, Vec<[u8;32]>) {
+            let (mut v, mut r, mut s) = (Vec::new(), Vec::new(), Vec::new());
+            for signer in signers {
+                match signer {
+                    Some(seed) => {
+                        let sig = SecretKeyRef::new(&secret(*seed)).sign(&hash, None).unwrap();
+                        v.push(sig.v);
+                        r.push(sig.r.into());
+                        s.push(sig.s.into());
+                    }
+                    None => {
+                        v.push(0);
+                        r.push([0u8;32]);
+                        s.push([0u8;32]);
+                    }
+                }
+            }
+            (v, r, s)
+        }
+
+        fn new_nebula(data_type: DataType, oracles: Vec<[u8;32]>) -> Nebula {
+            let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                .expect("Cannot get accounts");
+            Nebula::new(data_type, accounts.django, 2, oracles, CHAIN_ID)
+        }
+
+        #[ink::test]
+        fn pulses_are_numbered_from_one() {
+            let mut nebula = new_nebula(DataType::Bytes, vec![oracle(1), oracle(2), oracle(3)]);
+            let first_hash = keccak256(b"first");
+            let (v, r, s) = sign(nebula.hash_pulse(first_hash), &[Some(1), None, Some(3)]);
+            assert_eq!(nebula.send_hash_value(first_hash, v, r, s), Ok(1));
+            let second_hash = keccak256(b"second");
+            let (v, r, s) = sign(nebula.hash_pulse(second_hash), &[Some(1), Some(2), None]);
+            assert_eq!(nebula.send_hash_value(second_hash, v, r, s), Ok(2));
+            assert_eq!(nebula.get_last_pulse_id(), 2);
+            assert_eq!(nebula.get_pulse_hash(1), Some(first_hash));
+            assert_eq!(nebula.get_pulse_hash(2), Some(second_hash));
+        }
+
+        #[ink::test]
+        fn same_pulse_can_not_be_sent_twice() {
+            let mut nebula = new_nebula(DataType::Bytes, vec![oracle(1), oracle(2), oracle(3)]);
+            let data_hash = keccak256(b"value");
+            let (v, r, s) = sign(nebula.hash_pulse(data_hash), &[Some(1), Some(2), None]);
+            assert_eq!(nebula.send_hash_value(data_hash, v.clone(), r.clone(), s.clone()), Ok(1));
+            assert_eq!(nebula.send_hash_value(data_hash, v, r, s), Err(Error::PulseExists));
+            assert_eq!(nebula.get_last_pulse_id(), 1);
+        }
+
+        #[ink::test]
+        fn pulse_needs_enough_distinct_oracles() {
+            let mut nebula = new_nebula(DataType::Bytes, vec![oracle(1), oracle(1), oracle(2)]);
+            let data_hash = keccak256(b"value");
+            let hash = nebula.hash_pulse(data_hash);
+            let (v, r, s) = sign(hash, &[Some(1), None, None]);
+            assert_eq!(nebula.send_hash_value(data_hash, v, r, s), Err(Error::OraclesReduce));
+            // the same oracle listed twice is counted once
+            let (v, r, s) = sign(hash, &[Some(1), Some(1), None]);
+            assert_eq!(nebula.send_hash_value(data_hash, v, r, s), Err(Error::OraclesReduce));
+            // a signature of the raw data hash is not tied to this contract and is not counted
+            let (v, r, s) = sign(data_hash, &[Some(1), None, Some(2)]);
+            assert_eq!(nebula.send_hash_value(data_hash, v, r, s), Err(Error::OraclesReduce));
+            assert_eq!(nebula.get_last_pulse_id(), 0);
+        }
+
+        #[ink::test]
+        fn pulse_signed_for_another_chain_is_rejected() {
+            let oracles = vec![oracle(1), oracle(2), oracle(3)];
+            let mut nebula = new_nebula(DataType::Bytes, oracles.clone());
+            let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                .expect("Cannot get accounts");
+            let other_chain = Nebula::new(DataType::Bytes, accounts.django, 2, oracles, CHAIN_ID + 1);
+            assert_ne!(other_chain.domain_separator(), nebula.domain_separator());
+            let data_hash = keccak256(b"value");
+            let (v, r, s) = sign(other_chain.hash_pulse(data_hash), &[Some(1), Some(2), Some(3)]);
+            assert_eq!(nebula.send_hash_value(data_hash, v, r, s), Err(Error::OraclesReduce));
+        }
+
+        #[ink::test]
+        fn mismatched_signature_lengths_are_rejected() {
+            let mut nebula = new_nebula(DataType::Bytes, vec![oracle(1), oracle(2), oracle(3)]);
+            let data_hash = keccak256(b"value");
+            let (v, r, s) = sign(nebula.hash_pulse(data_hash), &[Some(1), Some(2)]);
+            assert_eq!(nebula.send_hash_value(data_hash, v, r, s), Err(Error::SignaturesLengthMismatch));
+        }
+
+        /// sends a pulse for `value` and subscribes django to it, returns the pulse and subscription ids
+        fn pulse_with_subscriber(nebula: &mut Nebula, value: &[u8], minimal_comformations: u64) -> (u64, [u8;32]) {
+            let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                .expect("Cannot get accounts");
+            let data_hash = keccak256(value);
+            let (v, r, s) = sign(nebula.hash_pulse(data_hash), &[Some(1), Some(2), Some(3)]);
+            let pulse_id = nebula.send_hash_value(data_hash, v, r, s).unwrap();
+            // `subscribe` derives the id from the call input, which the off-chain env does not provide
+            let sub_id = keccak256(b"subscription");
+            nebula.subscriptions.insert(sub_id, Subscription{
+                owner: accounts.alice,
+                contact_address: accounts.django,
+                minimal_comformations: minimal_comformations,
+                reward: [0u8;32],
+            });
+            (pulse_id, sub_id)
+        }
+
+        fn advance_blocks(count: u32) {
+            for _ in 0..count {
+                ink_env::test::advance_block::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot advance block");
+            }
+        }
+
+        #[ink::test]
+        fn value_has_to_match_the_pulse() {
+            let mut nebula = new_nebula(DataType::Bytes, vec![oracle(1), oracle(2), oracle(3)]);
+            let (pulse_id, sub_id) = pulse_with_subscriber(&mut nebula, b"value", 0);
+            assert_eq!(nebula.send_value_to_subs(b"other".to_vec(), pulse_id, sub_id), Err(Error::InvalidValueHash));
+            assert!(!nebula.is_value_sent(pulse_id, sub_id));
+        }
+
+        #[ink::test]
+        fn value_waits_for_confirmations() {
+            let mut nebula = new_nebula(DataType::Bytes, vec![oracle(1), oracle(2), oracle(3)]);
+            let (pulse_id, sub_id) = pulse_with_subscriber(&mut nebula, b"value", 2);
+            advance_blocks(1);
+            assert_eq!(nebula.send_value_to_subs(b"value".to_vec(), pulse_id, sub_id), Err(Error::NotEnoughConfirmations));
+            assert!(!nebula.is_value_sent(pulse_id, sub_id));
+        }
+
+        #[ink::test]
+        fn value_is_sent_once() {
+            let mut nebula = new_nebula(DataType::Bytes, vec![oracle(1), oracle(2), oracle(3)]);
+            let (pulse_id, sub_id) = pulse_with_subscriber(&mut nebula, b"value", 0);
+            nebula.is_pulse_sub_sent.insert((pulse_id, sub_id), true);
+            assert_eq!(nebula.send_value_to_subs(b"value".to_vec(), pulse_id, sub_id), Err(Error::ValueAlreadySent));
+        }
+
+        #[ink::test]
+        fn int_value_needs_eight_bytes() {
+            let mut nebula = new_nebula(DataType::Int64, vec![oracle(1), oracle(2), oracle(3)]);
+            let value = [0u8;4];
+            let (pulse_id, sub_id) = pulse_with_subscriber(&mut nebula, &value, 0);
+            assert_eq!(nebula.send_value_to_subs(value.to_vec(), pulse_id, sub_id), Err(Error::InvalidValueType));
+            assert!(!nebula.is_value_sent(pulse_id, sub_id));
+        }
+
+        #[ink::test]
+        fn string_value_has_to_be_utf8() {
+            let mut nebula = new_nebula(DataType::Str, vec![oracle(1), oracle(2), oracle(3)]);
+            let value = [0xFFu8, 0xFE, 0xFD];
+            let (pulse_id, sub_id) = pulse_with_subscriber(&mut nebula, &value, 0);
+            assert_eq!(nebula.send_value_to_subs(value.to_vec(), pulse_id, sub_id), Err(Error::InvalidValueType));
+            assert!(!nebula.is_value_sent(pulse_id, sub_id));
+        }
     }
 }
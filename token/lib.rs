@@ -5,9 +5,19 @@ pub use self::token::Token;
 
 #[ink::contract]
 mod token {
-    use ink_storage::collections::{hashmap::Entry, HashMap, Vec};
-    use ink_prelude::string::String;
-    use std::convert::Into;
+    use ink_storage::{
+        collections::{HashMap, Vec as StorageVec},
+        traits::{pull_spread_root, push_spread_root, PackedLayout, SpreadLayout},
+    };
+    use ink_primitives::Key;
+    use ink_env::{
+        call::{build_call, utils::ReturnType, ExecutionInput, Selector},
+        ReturnFlags,
+    };
+    use ink_prelude::{string::String, vec::Vec};
+    use std::mem::transmute;
+    use web3::types::{Recovery,H160};
+    use web3::signing::{keccak256,recover};
 
     #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
     #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
@@ -15,69 +25,534 @@ mod token {
         NotOwner,
         NotEnoughMoney,
         NotFound,
+        InsufficientAllowance,
+        MissingRole,
+        Paused,
+        AccountFrozen,
+        CapExceeded,
+        QuotaExceeded,
+        Overflow,
+        PermitExpired,
+        InvalidSignature,
+        InvalidSnapshot,
+        TransferRejected,
+        FlashLoanRejected,
+        FlashLoanNotRepaid,
+        InvalidQuota,
     }
     pub type Result<T> = core::result::Result<T, Error>;
 
+    /// error of the messages behind PSP22 selectors, laid out as PSP22 defines it
+    /// so PSP22 callers can decode it
+    #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub enum PSP22Error {
+        Custom(String),
+        InsufficientBalance,
+        InsufficientAllowance,
+        ZeroRecipientAddress,
+        ZeroSenderAddress,
+        SafeTransferCheckFailed(String),
+    }
+    pub type PSP22Result<T> = core::result::Result<T, PSP22Error>;
+
+    /// errors without a PSP22 counterpart are passed on by name
+    impl From<Error> for PSP22Error {
+        fn from(err: Error) -> Self {
+            match err {
+                Error::NotEnoughMoney => PSP22Error::InsufficientBalance,
+                Error::InsufficientAllowance => PSP22Error::InsufficientAllowance,
+                err => PSP22Error::Custom(format!("{:?}", err)),
+            }
+        }
+    }
+
+    /// selector of `on_token_received(from: AccountId, ammount: u128, data: Vec<u8>)`,
+    /// which contracts must implement to accept `transfer_and_call`
+    pub const ON_TOKEN_RECEIVED_SELECTOR: [u8;4] = [0x41, 0xEB, 0x77, 0x88];
+
+    /// selector of `on_flash_loan(initiator: AccountId, ammount: u128, fee: u128, data: Vec<u8>)`,
+    /// which flash loan receivers must implement
+    pub const ON_FLASH_LOAN_SELECTOR: [u8;4] = [0x08, 0x48, 0x1E, 0x7F];
+
+    /// storage key of the contract root, where ink! keeps the `Token` struct
+    const ROOT_KEY: [u8;32] = [0x00;32];
+
+    /// flash loan fees are expressed in basis points of the borrowed amount
+    const FEE_DENOMINATOR: u128 = 10_000;
+
+    /// version of the signing domain used by `permit`
+    const PERMIT_VERSION: &[u8] = b"1";
+
+    /// Limits how much a single minter can mint during any `period` consecutive blocks.
+    /// Every mint is recorded with its block, records `first_record..next_record` of the
+    /// minter are the ones still inside the window and `minted` is their sum.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode, SpreadLayout, PackedLayout)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
+    )]
+    pub struct MintQuota {
+        pub limit: u128,
+        pub period: BlockNumber,
+        pub first_record: u32,
+        pub next_record: u32,
+        pub minted: u128,
+    }
+
+    pub type RoleType = u32;
+    /// may grant and revoke any role
+    pub const ADMIN: RoleType = 0;
+    /// may mint new tokens
+    pub const MINTER: RoleType = 1;
+    /// may burn tokens from any account
+    pub const BURNER: RoleType = 2;
+    /// may pause and unpause all balance changes
+    pub const PAUSER: RoleType = 3;
+
     #[ink(storage)]
     pub struct Token {
         token_name: String,
         token_symbol: String,
+        token_decimals: u8,
         balances: HashMap<AccountId, u128>,
+        allowances: HashMap<(AccountId, AccountId), u128>,
         total_supply: u128,
-        deployers: Vec<AccountId>,
+        cap: Option<u128>,
+        mint_quotas: HashMap<AccountId, MintQuota>,
+        /// `(minter, i)` is the `i`th mint of `minter` under its quota, as `(block, ammount)`
+        mint_records: HashMap<(AccountId, u32), (BlockNumber, u128)>,
+        roles: HashMap<(RoleType, AccountId), bool>,
+        paused: bool,
+        frozen_accounts: HashMap<AccountId, bool>,
+        nonces: HashMap<AccountId, u64>,
+        current_snapshot_id: u64,
+        snapshot_blocks: HashMap<u64, BlockNumber>,
+        /// balances before the first change after each snapshot, `(account, i)` is the `i`th
+        /// checkpoint of `account`, sorted by snapshot id
+        account_checkpoints: HashMap<(AccountId, u32), (u64, u128)>,
+        account_checkpoint_counts: HashMap<AccountId, u32>,
+        supply_checkpoints: StorageVec<(u64, u128)>,
+        flash_fee_bps: u128,
+        treasury: AccountId,
+        /// id of the chain this deployment lives on, part of the permit domain
+        chain_id: u64,
+    }
+
+    #[ink(event)]
+    pub struct Transfer {
+        #[ink(topic)]
+        from: Option<AccountId>,
+        #[ink(topic)]
+        to: Option<AccountId>,
+        value: u128,
+    }
+
+    #[ink(event)]
+    pub struct Approval {
+        #[ink(topic)]
+        owner: AccountId,
+        #[ink(topic)]
+        spender: AccountId,
+        value: u128,
+    }
+
+    #[ink(event)]
+    pub struct RoleGranted {
+        #[ink(topic)]
+        role: RoleType,
+        #[ink(topic)]
+        account: AccountId,
+        sender: AccountId,
+    }
+
+    #[ink(event)]
+    pub struct RoleRevoked {
+        #[ink(topic)]
+        role: RoleType,
+        #[ink(topic)]
+        account: AccountId,
+        sender: AccountId,
+    }
+
+    #[ink(event)]
+    pub struct Paused {
+        account: AccountId,
+    }
+
+    #[ink(event)]
+    pub struct Unpaused {
+        account: AccountId,
+    }
+
+    #[ink(event)]
+    pub struct Frozen {
+        #[ink(topic)]
+        account: AccountId,
+    }
+
+    #[ink(event)]
+    pub struct Unfrozen {
+        #[ink(topic)]
+        account: AccountId,
+    }
+
+    #[ink(event)]
+    pub struct Snapshot {
+        id: u64,
+        block: BlockNumber,
     }
 
     impl Token {
-        /// Constructor that initializes the `bool` value to the given `init_value`.
+        /// Creates a token with the given metadata, the caller gets the `ADMIN` role
+        /// and has to grant `MINTER` and `BURNER` explicitly, e.g. to the IBport.
+        /// `decimals` should match the asset on the foreign chain, e.g. 18 for bridged ERC-20s.
+        /// `cap` is a hard limit for `total_supply`, `None` means no limit.
+        /// `chain_id` binds permits to this chain, like the Gravity signing domain.
         #[ink(constructor)]
-        pub fn new(name: String, symbol: String) -> Self {
-            let mut deployers = Vec::new();
-            deployers.push(Self::env().caller());
+        pub fn new(name: String, symbol: String, decimals: u8, cap: Option<u128>, chain_id: u64) -> Self {
+            let caller = Self::env().caller();
+            let mut roles = HashMap::new();
+            roles.insert((ADMIN, caller), true);
             Self {
                 token_name: name,
                 token_symbol: symbol,
+                token_decimals: decimals,
                 balances: HashMap::new(),
-                deployers: deployers,
+                allowances: HashMap::new(),
                 total_supply: 0,
+                cap: cap,
+                mint_quotas: HashMap::new(),
+                mint_records: HashMap::new(),
+                roles: roles,
+                paused: false,
+                frozen_accounts: HashMap::new(),
+                nonces: HashMap::new(),
+                current_snapshot_id: 0,
+                snapshot_blocks: HashMap::new(),
+                account_checkpoints: HashMap::new(),
+                account_checkpoint_counts: HashMap::new(),
+                supply_checkpoints: StorageVec::new(),
+                flash_fee_bps: 0,
+                treasury: caller,
+                chain_id: chain_id,
             }
         }
 
-        fn is_deployer(&self, addr: AccountId) -> bool {
-            self.deployers.iter().any(| &i | i == addr)
+        #[ink(message)]
+        pub fn has_role(&self, role: RoleType, account: AccountId) -> bool {
+            self.roles
+                .get(&(role, account))
+                .cloned()
+                .unwrap_or(false)
+        }
+
+        fn ensure_role(&self, role: RoleType, account: AccountId) -> Result<()> {
+            if !self.has_role(role, account) {
+                return Err(Error::MissingRole);
+            }
+            Ok(())
         }
 
         #[ink(message)]
-        pub fn add_deployer(&mut self, adress: AccountId) -> Result<()> {
-            if !self.is_deployer(self.env().caller()) {
-                return Err(Error::NotOwner);
+        pub fn grant_role(&mut self, role: RoleType, account: AccountId) -> Result<()> {
+            let sender = self.env().caller();
+            self.ensure_role(ADMIN, sender)?;
+            if !self.has_role(role, account) {
+                self.roles.insert((role, account), true);
+                self.env().emit_event(RoleGranted {
+                    role: role,
+                    account: account,
+                    sender: sender,
+                });
             }
-            self.deployers.push(adress);
             Ok(())
         }
 
         #[ink(message)]
-        pub fn mint(&mut self, ammount: u128, adress: AccountId) -> Result<()> {
+        pub fn revoke_role(&mut self, role: RoleType, account: AccountId) -> Result<()> {
+            let sender = self.env().caller();
+            self.ensure_role(ADMIN, sender)?;
+            self.remove_role(role, account, sender);
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn renounce_role(&mut self, role: RoleType) -> Result<()> {
+            let sender = self.env().caller();
+            self.ensure_role(role, sender)?;
+            self.remove_role(role, sender, sender);
+            Ok(())
+        }
+
+        fn remove_role(&mut self, role: RoleType, account: AccountId, sender: AccountId) {
+            if self.roles.take(&(role, account)).is_some() {
+                self.env().emit_event(RoleRevoked {
+                    role: role,
+                    account: account,
+                    sender: sender,
+                });
+            }
+        }
+
+        #[ink(message)]
+        pub fn paused(&self) -> bool {
+            self.paused
+        }
+
+        fn ensure_not_paused(&self) -> Result<()> {
+            if self.paused {
+                return Err(Error::Paused);
+            }
+            Ok(())
+        }
+
+        /// Freezes transfers, mints and burns until `unpause` is called.
+        /// Queries keep working while paused.
+        #[ink(message)]
+        pub fn pause(&mut self) -> Result<()> {
             let sender = self.env().caller();
+            self.ensure_role(PAUSER, sender)?;
+            self.paused = true;
+            self.env().emit_event(Paused { account: sender });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn unpause(&mut self) -> Result<()> {
+            let sender = self.env().caller();
+            self.ensure_role(PAUSER, sender)?;
+            self.paused = false;
+            self.env().emit_event(Unpaused { account: sender });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn is_frozen(&self, account: AccountId) -> bool {
+            self.frozen_accounts
+                .get(&account)
+                .cloned()
+                .unwrap_or(false)
+        }
+
+        fn ensure_not_frozen(&self, account: AccountId) -> Result<()> {
+            if self.is_frozen(account) {
+                return Err(Error::AccountFrozen);
+            }
+            Ok(())
+        }
+
+        /// Frozen accounts can still receive tokens but can not send them or be burned from.
+        #[ink(message)]
+        pub fn freeze_account(&mut self, account: AccountId) -> Result<()> {
+            self.ensure_role(ADMIN, self.env().caller())?;
+            self.frozen_accounts.insert(account, true);
+            self.env().emit_event(Frozen { account: account });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn unfreeze_account(&mut self, account: AccountId) -> Result<()> {
+            self.ensure_role(ADMIN, self.env().caller())?;
+            if self.frozen_accounts.take(&account).is_some() {
+                self.env().emit_event(Unfrozen { account: account });
+            }
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn cap(&self) -> Option<u128> {
+            self.cap
+        }
+
+        fn ensure_within_cap(&self, ammount: u128) -> Result<()> {
+            if let Some(cap) = self.cap {
+                if self.total_supply.saturating_add(ammount) > cap {
+                    return Err(Error::CapExceeded);
+                }
+            }
+            Ok(())
+        }
+
+        /// Allows `minter` to mint at most `limit` tokens per `period` blocks, `period` can not be zero.
+        /// Mints already recorded under a previous quota keep counting.
+        #[ink(message)]
+        pub fn set_mint_quota(&mut self, minter: AccountId, limit: u128, period: BlockNumber) -> Result<()> {
+            self.ensure_role(ADMIN, self.env().caller())?;
+            if period == 0 {
+                return Err(Error::InvalidQuota);
+            }
+            let quota = match self.mint_quotas.get(&minter) {
+                Some(quota) => MintQuota { limit: limit, period: period, ..*quota },
+                None => MintQuota {
+                    limit: limit,
+                    period: period,
+                    first_record: 0,
+                    next_record: 0,
+                    minted: 0,
+                },
+            };
+            self.mint_quotas.insert(minter, quota);
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn remove_mint_quota(&mut self, minter: AccountId) -> Result<()> {
+            self.ensure_role(ADMIN, self.env().caller())?;
+            if let Some(quota) = self.mint_quotas.take(&minter) {
+                for i in quota.first_record..quota.next_record {
+                    self.mint_records.take(&(minter, i));
+                }
+            }
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn mint_quota(&self, minter: AccountId) -> Option<MintQuota> {
+            self.mint_quotas.get(&minter).cloned()
+        }
 
-            if self.is_deployer(sender) {
-                return Err(Error::NotOwner);
+        /// Amount `minter` can still mint in this block, `None` if it has no quota.
+        #[ink(message)]
+        pub fn remaining_mint_quota(&self, minter: AccountId) -> Option<u128> {
+            self.current_mint_quota(minter)
+                .map(|quota| quota.limit.saturating_sub(quota.minted))
+        }
+
+        /// returns the quota of `minter` without the records that left the window,
+        /// a record of block `b` counts until block `b + period`
+        fn current_mint_quota(&self, minter: AccountId) -> Option<MintQuota> {
+            let mut quota = self.mint_quotas.get(&minter).cloned()?;
+            let now = self.env().block_number();
+            while quota.first_record < quota.next_record {
+                let (block, ammount) = self.mint_record(minter, quota.first_record);
+                if now < block.saturating_add(quota.period) {
+                    break;
+                }
+                quota.minted -= ammount;
+                quota.first_record += 1;
             }
+            Some(quota)
+        }
 
-            match self.balances.entry(adress) {
-                Entry::Vacant(v) => {
-                    v.insert(ammount.into());
+        fn mint_record(&self, minter: AccountId, index: u32) -> (BlockNumber, u128) {
+            self.mint_records
+                .get(&(minter, index))
+                .cloned()
+                .expect("records between first_record and next_record are kept")
+        }
+
+        fn consume_mint_quota(&mut self, minter: AccountId, ammount: u128) -> Result<()> {
+            let stored_first = match self.mint_quotas.get(&minter) {
+                Some(quota) => quota.first_record,
+                None => return Ok(()),
+            };
+            let mut quota = self.current_mint_quota(minter).expect("the quota was just read");
+            let minted = quota.minted.saturating_add(ammount);
+            if minted > quota.limit {
+                return Err(Error::QuotaExceeded);
+            }
+            for i in stored_first..quota.first_record {
+                self.mint_records.take(&(minter, i));
+            }
+
+            // mints of the same block share one record
+            let now = self.env().block_number();
+            let last = quota.next_record.checked_sub(1).filter(|last| *last >= quota.first_record);
+            match last.map(|last| (last, self.mint_record(minter, last))) {
+                Some((last, (block, recorded))) if block == now => {
+                    self.mint_records.insert((minter, last), (now, recorded + ammount));
                 }
-                Entry::Occupied(mut o) => {
-                    let mut t = o.get().clone();
-                    t += ammount;
-                    o.insert(t);
+                _ => {
+                    self.mint_records.insert((minter, quota.next_record), (now, ammount));
+                    quota.next_record += 1;
                 }
             }
+            quota.minted = minted;
+            self.mint_quotas.insert(minter, quota);
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn mint(&mut self, ammount: u128, adress: AccountId) -> Result<()> {
+            let sender = self.env().caller();
+            self.ensure_can_mint(sender, ammount)?;
+            self.consume_mint_quota(sender, ammount)?;
+            self.mint_to(adress, ammount)
+        }
+
+        /// Mints to every `(account, ammount)` pair, either all of them or none.
+        /// Cap and quota are checked against the sum of the batch.
+        #[ink(message)]
+        pub fn batch_mint(&mut self, mints: Vec<(AccountId, u128)>) -> Result<()> {
+            let sender = self.env().caller();
+            let total = Self::batch_total(&mints)?;
+            self.ensure_can_mint(sender, total)?;
+            self.consume_mint_quota(sender, total)?;
+            for (adress, ammount) in mints {
+                self.mint_to(adress, ammount)?;
+            }
+            Ok(())
+        }
+
+        fn batch_total(entries: &[(AccountId, u128)]) -> Result<u128> {
+            entries
+                .iter()
+                .try_fold(0u128, |total, &(_, ammount)| total.checked_add(ammount))
+                .ok_or(Error::Overflow)
+        }
 
-            self.total_supply += ammount;
+        /// every check of minting except the quota, which is consumed separately
+        fn ensure_can_mint(&self, minter: AccountId, ammount: u128) -> Result<()> {
+            self.ensure_role(MINTER, minter)?;
+            self.ensure_not_paused()?;
+            self.ensure_within_cap(ammount)?;
+            self.total_supply
+                .checked_add(ammount)
+                .ok_or(Error::Overflow)?;
+            Ok(())
+        }
+
+        fn mint_to(&mut self, adress: AccountId, ammount: u128) -> Result<()> {
+            let total_supply = self.total_supply
+                .checked_add(ammount)
+                .ok_or(Error::Overflow)?;
+            let balance = self.balance_of(adress)
+                .checked_add(ammount)
+                .ok_or(Error::Overflow)?;
+
+            self.update_account_checkpoint(adress);
+            self.update_supply_checkpoint();
+            self.balances.insert(adress, balance);
+            self.total_supply = total_supply;
+            self.env().emit_event(Transfer {
+                from: None,
+                to: Some(adress),
+                value: ammount,
+            });
             Ok(())
         }
 
+        #[ink(message)]
+        pub fn token_name(&self) -> String {
+            self.token_name.clone()
+        }
+
+        #[ink(message)]
+        pub fn token_symbol(&self) -> String {
+            self.token_symbol.clone()
+        }
+
+        #[ink(message)]
+        pub fn token_decimals(&self) -> u8 {
+            self.token_decimals
+        }
+
+        #[ink(message)]
+        pub fn total_supply(&self) -> u128 {
+            self.total_supply
+        }
+
         #[ink(message)]
         pub fn balance_of(&self, account: AccountId) -> u128 {
             self
@@ -92,44 +567,484 @@ mod token {
             self.transfer_from_to(self.env().caller(), to, ammount.into())
         }
 
-        fn transfer_from_to(
+        /// Transfers to a contract and notifies it through `on_token_received`.
+        /// The whole call is reverted if the recipient fails or returns an error.
+        #[ink(message)]
+        pub fn transfer_and_call(&mut self, to: AccountId, ammount: u128, data: Vec<u8>) -> Result<()> {
+            let from = self.env().caller();
+            self.transfer_from_to(from, to, ammount)?;
+            self.flush_storage();
+
+            // recipients return `Result<(), E>` where `E` is a fieldless enum,
+            // so only the discriminant of the error is decoded here
+            let res = build_call::<<Self as ::ink_lang::ContractEnv>::Env>()
+                .callee(to)
+                .gas_limit(0)
+                .transferred_value(0)
+                .exec_input(
+                    ExecutionInput::new(Selector::new(ON_TOKEN_RECEIVED_SELECTOR))
+                        .push_arg(from)
+                        .push_arg(ammount)
+                        .push_arg(data)
+                )
+                .returns::<ReturnType<core::result::Result<(), u8>>>()
+                .fire();
+            self.reload_storage();
+            match res {
+                Ok(Ok(())) => Ok(()),
+                _ => Self::revert(Error::TransferRejected),
+            }
+        }
+
+        /// ink! only writes storage when a message returns, so it is written before calling
+        /// another contract. A re-entrant call then sees the current balances.
+        fn flush_storage(&self) {
+            push_spread_root::<Self>(self, &Key::from(ROOT_KEY));
+        }
+
+        /// reads storage back after calling another contract, so the changes of a re-entrant
+        /// call are kept instead of being overwritten when this message returns
+        fn reload_storage(&mut self) {
+            *self = pull_spread_root::<Self>(&Key::from(ROOT_KEY));
+        }
+
+        #[ink(message)]
+        pub fn treasury(&self) -> AccountId {
+            self.treasury
+        }
+
+        #[ink(message)]
+        pub fn set_treasury(&mut self, treasury: AccountId) -> Result<()> {
+            self.ensure_role(ADMIN, self.env().caller())?;
+            self.treasury = treasury;
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn flash_fee_bps(&self) -> u128 {
+            self.flash_fee_bps
+        }
+
+        #[ink(message)]
+        pub fn set_flash_fee_bps(&mut self, fee_bps: u128) -> Result<()> {
+            self.ensure_role(ADMIN, self.env().caller())?;
+            self.flash_fee_bps = fee_bps;
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn flash_fee(&self, ammount: u128) -> Result<u128> {
+            ammount
+                .checked_mul(self.flash_fee_bps)
+                .map(|fee| fee / FEE_DENOMINATOR)
+                .ok_or(Error::Overflow)
+        }
+
+        /// the largest amount that can be flash minted without hitting the cap
+        #[ink(message)]
+        pub fn max_flash_loan(&self) -> u128 {
+            self.cap.unwrap_or(u128::MAX).saturating_sub(self.total_supply)
+        }
+
+        /// Mints `ammount` to `receiver` and calls its `on_flash_loan`. Before returning the
+        /// receiver must have approved this contract for `ammount` plus the fee: the amount is
+        /// burned back and the fee is sent to the treasury, otherwise the whole call is reverted.
+        /// Storage is written before the callback, so the receiver can spend the loan in it.
+        #[ink(message)]
+        pub fn flash_loan(&mut self, receiver: AccountId, ammount: u128, data: Vec<u8>) -> Result<()> {
+            let initiator = self.env().caller();
+            self.ensure_not_paused()?;
+            self.ensure_not_frozen(receiver)?;
+            self.ensure_within_cap(ammount)?;
+            let fee = self.flash_fee(ammount)?;
+            let repayment = ammount.checked_add(fee).ok_or(Error::Overflow)?;
+            self.mint_to(receiver, ammount)?;
+            self.flush_storage();
+
+            let res = build_call::<<Self as ::ink_lang::ContractEnv>::Env>()
+                .callee(receiver)
+                .gas_limit(0)
+                .transferred_value(0)
+                .exec_input(
+                    ExecutionInput::new(Selector::new(ON_FLASH_LOAN_SELECTOR))
+                        .push_arg(initiator)
+                        .push_arg(ammount)
+                        .push_arg(fee)
+                        .push_arg(data)
+                )
+                .returns::<ReturnType<core::result::Result<(), u8>>>()
+                .fire();
+            self.reload_storage();
+            if !matches!(res, Ok(Ok(()))) {
+                Self::revert(Error::FlashLoanRejected);
+            }
+
+            if self.repay_flash_loan(receiver, ammount, fee, repayment).is_err() {
+                Self::revert(Error::FlashLoanNotRepaid);
+            }
+            Ok(())
+        }
+
+        fn repay_flash_loan(&mut self, receiver: AccountId, ammount: u128, fee: u128, repayment: u128) -> Result<()> {
+            let this = self.env().account_id();
+            let allowance = self.allowance(receiver, this);
+            if allowance < repayment {
+                return Err(Error::InsufficientAllowance);
+            }
+            self.set_allowance(receiver, this, allowance - repayment);
+            self.burn_from(receiver, ammount)?;
+            if fee > 0 {
+                let treasury = self.treasury;
+                self.transfer_from_to(receiver, treasury, fee)?;
+            }
+            Ok(())
+        }
+
+        /// returns `err` with the revert flag set, so every change made during the call is rolled back
+        fn revert(err: Error) -> ! {
+            ink_env::return_value::<Result<()>>(ReturnFlags::default().set_reverted(true), &Err(err))
+        }
+
+        /// Sends every `(account, ammount)` pair from the caller, either all of them or none.
+        #[ink(message)]
+        pub fn batch_transfer(&mut self, transfers: Vec<(AccountId, u128)>) -> Result<()> {
+            let sender = self.env().caller();
+            self.ensure_not_paused()?;
+            self.ensure_not_frozen(sender)?;
+            let total = Self::batch_total(&transfers)?;
+            if self.balance_of(sender) < total {
+                return Err(Error::NotEnoughMoney);
+            }
+            for (to, ammount) in transfers {
+                self.transfer_from_to(sender, to, ammount)?;
+            }
+            Ok(())
+        }
+
+        /// the allowance messages use the PSP22 selectors and return `PSP22Error`,
+        /// so wallets speaking PSP22 can call them
+        #[ink(message, selector = "0x4D47D921")]
+        pub fn allowance(&self, owner: AccountId, spender: AccountId) -> u128 {
+            self
+                .allowances
+                .get(&(owner, spender))
+                .unwrap_or(&0u128)
+                .clone()
+        }
+
+        fn set_allowance(&mut self, owner: AccountId, spender: AccountId, ammount: u128) {
+            self.allowances.insert((owner, spender), ammount);
+            self.env().emit_event(Approval {
+                owner: owner,
+                spender: spender,
+                value: ammount,
+            });
+        }
+
+        #[ink(message, selector = "0xB20F1BBD")]
+        pub fn approve(&mut self, spender: AccountId, ammount: u128) -> PSP22Result<()> {
+            let owner = self.env().caller();
+            self.set_allowance(owner, spender, ammount);
+            Ok(())
+        }
+
+        #[ink(message, selector = "0x96D6B57A")]
+        pub fn increase_allowance(&mut self, spender: AccountId, ammount: u128) -> PSP22Result<()> {
+            let owner = self.env().caller();
+            let allowance = self.allowance(owner, spender);
+            let allowance = allowance.checked_add(ammount).ok_or(Error::Overflow)?;
+            self.set_allowance(owner, spender, allowance);
+            Ok(())
+        }
+
+        #[ink(message, selector = "0xFECB57D5")]
+        pub fn decrease_allowance(&mut self, spender: AccountId, ammount: u128) -> PSP22Result<()> {
+            let owner = self.env().caller();
+            let allowance = self.allowance(owner, spender);
+            if allowance < ammount {
+                return Err(PSP22Error::InsufficientAllowance);
+            }
+            self.set_allowance(owner, spender, allowance - ammount);
+            Ok(())
+        }
+
+        /// `data` is only there to match the PSP22 signature and is ignored
+        #[ink(message, selector = "0x54B3C76E")]
+        pub fn transfer_from(&mut self, from: AccountId, to: AccountId, ammount: u128, data: Vec<u8>) -> PSP22Result<()> {
+            let _ = data;
+            let spender = self.env().caller();
+            let allowance = self.allowance(from, spender);
+            if allowance < ammount {
+                return Err(PSP22Error::InsufficientAllowance);
+            }
+            self.transfer_from_to(from, to, ammount)?;
+            self.set_allowance(from, spender, allowance - ammount);
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn nonces(&self, owner: AccountId) -> u64 {
+            self.nonces
+                .get(&owner)
+                .cloned()
+                .unwrap_or(0)
+        }
+
+        #[ink(message)]
+        pub fn get_chain_id(&self) -> u64 {
+            self.chain_id
+        }
+
+        /// EIP-712 domain of this token, binds permits to the token name,
+        /// `PERMIT_VERSION`, the chain id and the address of this contract
+        #[ink(message)]
+        pub fn domain_separator(&self) -> [u8;32] {
+            let contract: [u8;32] = unsafe { transmute(self.env().account_id()) };
+            let mut data: Vec<u8> = Vec::new();
+            data.extend_from_slice(&keccak256(b"EIP712Domain(string name,string version,uint256 chainId,bytes32 verifyingContract)"));
+            data.extend_from_slice(&keccak256(self.token_name.as_bytes()));
+            data.extend_from_slice(&keccak256(PERMIT_VERSION));
+            data.extend_from_slice(&[0u8;24]);
+            data.extend_from_slice(&self.chain_id.to_be_bytes());
+            data.extend_from_slice(&contract);
+            keccak256(&data[..])
+        }
+
+        /// the hash `owner` has to sign to let `spender` use `ammount` of its tokens,
+        /// the current nonce of `owner` is included so every permit can be used once
+        #[ink(message)]
+        pub fn hash_permit(
+            &self,
+            owner: AccountId,
+            spender: AccountId,
+            ammount: u128,
+            deadline: Timestamp,
+        ) -> [u8;32] {
+            let owner_bytes: [u8;32] = unsafe { transmute(owner) };
+            let spender_bytes: [u8;32] = unsafe { transmute(spender) };
+
+            // numbers are encoded as big-endian uint256
+            let mut struct_data: Vec<u8> = Vec::new();
+            struct_data.extend_from_slice(&keccak256(
+                b"Permit(bytes32 owner,bytes32 spender,uint256 value,uint256 nonce,uint256 deadline)"
+            ));
+            struct_data.extend_from_slice(&owner_bytes);
+            struct_data.extend_from_slice(&spender_bytes);
+            struct_data.extend_from_slice(&[0u8;16]);
+            struct_data.extend_from_slice(&ammount.to_be_bytes());
+            struct_data.extend_from_slice(&[0u8;24]);
+            struct_data.extend_from_slice(&self.nonces(owner).to_be_bytes());
+            struct_data.extend_from_slice(&[0u8;24]);
+            struct_data.extend_from_slice(&deadline.to_be_bytes());
+
+            let mut data: Vec<u8> = Vec::new();
+            data.extend_from_slice(b"\x19\x01");
+            data.extend_from_slice(&self.domain_separator());
+            data.extend_from_slice(&keccak256(&struct_data[..]));
+            keccak256(&data[..])
+        }
+
+        /// Sets the allowance of `spender` over the tokens of `owner` by a secp256k1 signature
+        /// of `hash_permit`, so anyone can relay the approval. The first 20 bytes of `owner`
+        /// are its ethereum address, the same way Gravity stores consuls.
+        #[ink(message)]
+        pub fn permit(
             &mut self,
-            from: AccountId,
-            to: AccountId,
+            owner: AccountId,
+            spender: AccountId,
             ammount: u128,
+            deadline: Timestamp,
+            v: u64,
+            r: [u8;32],
+            s: [u8;32],
         ) -> Result<()> {
-            let sender_balance = self.balance_of(from);
-            let to_balance = self.balance_of(to);
+            if self.env().block_timestamp() > deadline {
+                return Err(Error::PermitExpired);
+            }
 
-            if sender_balance < ammount {
-                return Err(Error::NotEnoughMoney);
+            let hash = self.hash_permit(owner, spender, ammount, deadline);
+            let rec = Recovery::new(hash, v, r.into(), s.into())
+                .as_signature()
+                .ok_or(Error::InvalidSignature)?;
+            let addr = recover(&hash, &rec.0, rec.1).map_err(|_| Error::InvalidSignature)?;
+            let owner_bytes: [u8;32] = unsafe { transmute(owner) };
+            if addr != H160::from_slice(&owner_bytes[0..20]) {
+                return Err(Error::InvalidSignature);
             }
 
-            self
-                .balances
-                .insert(from, sender_balance - ammount);
-            self
-                .balances
-                .insert(to, to_balance + ammount);
+            let nonce = self.nonces(owner).checked_add(1).ok_or(Error::Overflow)?;
+            self.nonces.insert(owner, nonce);
+            self.set_allowance(owner, spender, ammount);
             Ok(())
         }
 
+        /// Records a new snapshot at the current block and returns its id.
+        /// Balances are checkpointed lazily on the first change after a snapshot.
         #[ink(message)]
-        pub fn burn_tokens(&mut self, account: AccountId, ammount: u128) -> Result<()> {
-            let sender = self.env().caller();
+        pub fn snapshot(&mut self) -> Result<u64> {
+            self.ensure_role(ADMIN, self.env().caller())?;
+            let id = self.current_snapshot_id.checked_add(1).ok_or(Error::Overflow)?;
+            let block = self.env().block_number();
+            self.current_snapshot_id = id;
+            self.snapshot_blocks.insert(id, block);
+            self.env().emit_event(Snapshot {
+                id: id,
+                block: block,
+            });
+            Ok(id)
+        }
+
+        #[ink(message)]
+        pub fn current_snapshot_id(&self) -> u64 {
+            self.current_snapshot_id
+        }
 
-            if self.is_deployer(sender) {
-                return Err(Error::NotOwner);
+        #[ink(message)]
+        pub fn snapshot_block(&self, snapshot_id: u64) -> Option<BlockNumber> {
+            self.snapshot_blocks.get(&snapshot_id).cloned()
+        }
+
+        #[ink(message)]
+        pub fn balance_of_at(&self, account: AccountId, snapshot_id: u64) -> Result<u128> {
+            self.ensure_snapshot_exists(snapshot_id)?;
+            let count = self.account_checkpoint_count(account);
+            let value = Self::checkpoint_value(count, |i| self.account_checkpoint(account, i), snapshot_id);
+            Ok(value.unwrap_or_else(|| self.balance_of(account)))
+        }
+
+        #[ink(message)]
+        pub fn total_supply_at(&self, snapshot_id: u64) -> Result<u128> {
+            self.ensure_snapshot_exists(snapshot_id)?;
+            let value = Self::checkpoint_value(
+                self.supply_checkpoints.len(),
+                |i| self.supply_checkpoints[i],
+                snapshot_id,
+            );
+            Ok(value.unwrap_or(self.total_supply))
+        }
+
+        fn ensure_snapshot_exists(&self, snapshot_id: u64) -> Result<()> {
+            if snapshot_id == 0 || snapshot_id > self.current_snapshot_id {
+                return Err(Error::InvalidSnapshot);
             }
+            Ok(())
+        }
 
-            let account_balance = self.balance_of(account);
-            if account_balance < ammount {
-                return Err(Error::NotEnoughMoney);
+        /// The first of the `len` checkpoints taken at or after `snapshot_id` holds the value at
+        /// that snapshot, `None` means the value has not changed since. Checkpoints are looked up
+        /// one by one, so only the ones the binary search visits are read from storage.
+        fn checkpoint_value<F: Fn(u32) -> (u64, u128)>(len: u32, checkpoint: F, snapshot_id: u64) -> Option<u128> {
+            let (mut low, mut high) = (0, len);
+            while low < high {
+                let mid = low + (high - low) / 2;
+                if checkpoint(mid).0 < snapshot_id {
+                    low = mid + 1;
+                } else {
+                    high = mid;
+                }
+            }
+            if low < len { Some(checkpoint(low).1) } else { None }
+        }
+
+        fn needs_checkpoint(last: Option<(u64, u128)>, snapshot_id: u64) -> bool {
+            snapshot_id > 0 && last.map_or(true, |(id, _)| id < snapshot_id)
+        }
+
+        fn account_checkpoint_count(&self, account: AccountId) -> u32 {
+            self.account_checkpoint_counts
+                .get(&account)
+                .cloned()
+                .unwrap_or(0)
+        }
+
+        fn account_checkpoint(&self, account: AccountId, index: u32) -> (u64, u128) {
+            self.account_checkpoints
+                .get(&(account, index))
+                .cloned()
+                .expect("every counted checkpoint is stored")
+        }
+
+        /// must be called before the balance of `account` changes
+        fn update_account_checkpoint(&mut self, account: AccountId) {
+            let snapshot_id = self.current_snapshot_id;
+            let count = self.account_checkpoint_count(account);
+            let last = if count > 0 { Some(self.account_checkpoint(account, count - 1)) } else { None };
+            if Self::needs_checkpoint(last, snapshot_id) {
+                let balance = self.balance_of(account);
+                self.account_checkpoints.insert((account, count), (snapshot_id, balance));
+                self.account_checkpoint_counts.insert(account, count + 1);
             }
+        }
 
-            self.balances.insert(account, account_balance - ammount);
+        /// must be called before `total_supply` changes
+        fn update_supply_checkpoint(&mut self) {
+            let snapshot_id = self.current_snapshot_id;
+            if Self::needs_checkpoint(self.supply_checkpoints.last().cloned(), snapshot_id) {
+                let total_supply = self.total_supply;
+                self.supply_checkpoints.push((snapshot_id, total_supply));
+            }
+        }
 
-            self.total_supply -= ammount;
+        fn transfer_from_to(
+            &mut self,
+            from: AccountId,
+            to: AccountId,
+            ammount: u128,
+        ) -> Result<()> {
+            self.ensure_not_paused()?;
+            self.ensure_not_frozen(from)?;
+            let sender_balance = self.balance_of(from)
+                .checked_sub(ammount)
+                .ok_or(Error::NotEnoughMoney)?;
+
+            // a transfer to self must not touch the balance at all
+            if from != to {
+                let to_balance = self.balance_of(to)
+                    .checked_add(ammount)
+                    .ok_or(Error::Overflow)?;
+                self.update_account_checkpoint(from);
+                self.update_account_checkpoint(to);
+                self
+                    .balances
+                    .insert(from, sender_balance);
+                self
+                    .balances
+                    .insert(to, to_balance);
+            }
+            self.env().emit_event(Transfer {
+                from: Some(from),
+                to: Some(to),
+                value: ammount,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn burn_tokens(&mut self, account: AccountId, ammount: u128) -> Result<()> {
+            let sender = self.env().caller();
+            self.ensure_role(BURNER, sender)?;
+            self.ensure_not_paused()?;
+            self.ensure_not_frozen(account)?;
+            self.burn_from(account, ammount)
+        }
+
+        fn burn_from(&mut self, account: AccountId, ammount: u128) -> Result<()> {
+            let account_balance = self.balance_of(account)
+                .checked_sub(ammount)
+                .ok_or(Error::NotEnoughMoney)?;
+            let total_supply = self.total_supply
+                .checked_sub(ammount)
+                .ok_or(Error::Overflow)?;
+
+            self.update_account_checkpoint(account);
+            self.update_supply_checkpoint();
+            self.balances.insert(account, account_balance);
+            self.total_supply = total_supply;
+            self.env().emit_event(Transfer {
+                from: Some(account),
+                to: None,
+                value: ammount,
+            });
             Ok(())
         }
     }
@@ -137,9 +1052,566 @@ mod token {
     mod tests {
         /// Imports all the definitions from the outer scope so we can use them here.
         use super::*;
+        use ink_lang as ink;
 
         /// We test if the default constructor does its job.
         #[test]
         fn default_works() {}
+
+        const CHAIN_ID: u64 = 42;
+
+        fn default_accounts() -> ink_env::test::DefaultAccounts<ink_env::DefaultEnvironment> {
+            ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                .expect("Cannot get accounts")
+        }
+
+        /// Creates a token where the caller (alice) may mint and burn.
+        fn new_token() -> Token {
+            let accounts = default_accounts();
+            let mut token = Token::new(String::from("Wrapped"), String::from("WRP"), 18, None, CHAIN_ID);
+            token.grant_role(MINTER, accounts.alice).unwrap();
+            token.grant_role(BURNER, accounts.alice).unwrap();
+            token
+        }
+
+        #[ink::test]
+        fn mint_up_to_u128_max_works() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            assert_eq!(token.mint(u128::MAX, accounts.alice), Ok(()));
+            assert_eq!(token.balance_of(accounts.alice), u128::MAX);
+            assert_eq!(token.total_supply(), u128::MAX);
+        }
+
+        #[ink::test]
+        fn mint_past_u128_max_overflows() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            token.mint(u128::MAX, accounts.alice).unwrap();
+            assert_eq!(token.mint(1, accounts.alice), Err(Error::Overflow));
+            assert_eq!(token.balance_of(accounts.alice), u128::MAX);
+            assert_eq!(token.total_supply(), u128::MAX);
+        }
+
+        #[ink::test]
+        fn mint_to_other_account_past_u128_max_supply_overflows() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            token.mint(u128::MAX, accounts.alice).unwrap();
+            assert_eq!(token.mint(1, accounts.bob), Err(Error::Overflow));
+            assert_eq!(token.balance_of(accounts.bob), 0);
+            assert_eq!(token.total_supply(), u128::MAX);
+        }
+
+        #[ink::test]
+        fn transfer_whole_u128_max_balance_works() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            token.mint(u128::MAX, accounts.alice).unwrap();
+            assert_eq!(token.transfer_tokens(accounts.bob, u128::MAX), Ok(()));
+            assert_eq!(token.balance_of(accounts.alice), 0);
+            assert_eq!(token.balance_of(accounts.bob), u128::MAX);
+        }
+
+        #[ink::test]
+        fn transfer_more_than_balance_fails() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            token.mint(u128::MAX - 1, accounts.alice).unwrap();
+            assert_eq!(token.transfer_tokens(accounts.bob, u128::MAX), Err(Error::NotEnoughMoney));
+            assert_eq!(token.balance_of(accounts.alice), u128::MAX - 1);
+            assert_eq!(token.balance_of(accounts.bob), 0);
+        }
+
+        #[ink::test]
+        fn transfer_to_self_keeps_balance() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            token.mint(u128::MAX, accounts.alice).unwrap();
+            assert_eq!(token.transfer_tokens(accounts.alice, u128::MAX), Ok(()));
+            assert_eq!(token.balance_of(accounts.alice), u128::MAX);
+        }
+
+        #[ink::test]
+        fn burn_whole_u128_max_balance_works() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            token.mint(u128::MAX, accounts.alice).unwrap();
+            assert_eq!(token.burn_tokens(accounts.alice, u128::MAX), Ok(()));
+            assert_eq!(token.balance_of(accounts.alice), 0);
+            assert_eq!(token.total_supply(), 0);
+        }
+
+        #[ink::test]
+        fn burn_more_than_balance_fails() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            token.mint(u128::MAX, accounts.alice).unwrap();
+            token.transfer_tokens(accounts.bob, 1).unwrap();
+            assert_eq!(token.burn_tokens(accounts.alice, u128::MAX), Err(Error::NotEnoughMoney));
+            assert_eq!(token.balance_of(accounts.alice), u128::MAX - 1);
+            assert_eq!(token.total_supply(), u128::MAX);
+        }
+
+        /// makes the following calls come from `caller`
+        fn set_caller(caller: AccountId) {
+            let callee = ink_env::account_id::<ink_env::DefaultEnvironment>()
+                .unwrap_or([0x0; 32].into());
+            let data = ink_env::test::CallData::new(ink_env::call::Selector::new([0x00; 4]));
+            ink_env::test::push_execution_context::<ink_env::DefaultEnvironment>(
+                caller, callee, 1000000, 1000000, data,
+            );
+        }
+
+        #[ink::test]
+        fn mint_and_burn_need_their_roles() {
+            let accounts = default_accounts();
+            let mut token = Token::new(String::from("Wrapped"), String::from("WRP"), 18, None, CHAIN_ID);
+            assert_eq!(token.mint(100, accounts.alice), Err(Error::MissingRole));
+            token.grant_role(MINTER, accounts.alice).unwrap();
+            assert_eq!(token.mint(100, accounts.alice), Ok(()));
+            assert_eq!(token.burn_tokens(accounts.alice, 10), Err(Error::MissingRole));
+            token.grant_role(BURNER, accounts.alice).unwrap();
+            assert_eq!(token.burn_tokens(accounts.alice, 10), Ok(()));
+
+            token.revoke_role(MINTER, accounts.alice).unwrap();
+            assert!(!token.has_role(MINTER, accounts.alice));
+            assert_eq!(token.mint(100, accounts.alice), Err(Error::MissingRole));
+            assert_eq!(token.balance_of(accounts.alice), 90);
+        }
+
+        #[ink::test]
+        fn only_admin_grants_and_revokes() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            set_caller(accounts.bob);
+            assert_eq!(token.grant_role(MINTER, accounts.bob), Err(Error::MissingRole));
+            assert_eq!(token.revoke_role(MINTER, accounts.alice), Err(Error::MissingRole));
+            assert!(!token.has_role(MINTER, accounts.bob));
+            assert!(token.has_role(MINTER, accounts.alice));
+
+            set_caller(accounts.alice);
+            assert_eq!(token.grant_role(ADMIN, accounts.bob), Ok(()));
+            set_caller(accounts.bob);
+            assert_eq!(token.revoke_role(MINTER, accounts.alice), Ok(()));
+            assert!(!token.has_role(MINTER, accounts.alice));
+        }
+
+        #[ink::test]
+        fn renounced_role_is_gone() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            assert_eq!(token.renounce_role(MINTER), Ok(()));
+            assert!(!token.has_role(MINTER, accounts.alice));
+            assert_eq!(token.renounce_role(MINTER), Err(Error::MissingRole));
+            assert_eq!(token.mint(1, accounts.alice), Err(Error::MissingRole));
+            assert!(token.has_role(ADMIN, accounts.alice));
+        }
+
+        #[ink::test]
+        fn pause_stops_balance_changes_but_not_queries() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            token.mint(100, accounts.alice).unwrap();
+            assert_eq!(token.pause(), Err(Error::MissingRole));
+            token.grant_role(PAUSER, accounts.alice).unwrap();
+            assert_eq!(token.pause(), Ok(()));
+            assert!(token.paused());
+
+            assert_eq!(token.transfer_tokens(accounts.bob, 10), Err(Error::Paused));
+            assert_eq!(token.mint(10, accounts.alice), Err(Error::Paused));
+            assert_eq!(token.burn_tokens(accounts.alice, 10), Err(Error::Paused));
+            assert_eq!(token.balance_of(accounts.alice), 100);
+            assert_eq!(token.total_supply(), 100);
+
+            set_caller(accounts.bob);
+            assert_eq!(token.unpause(), Err(Error::MissingRole));
+            set_caller(accounts.alice);
+            assert_eq!(token.unpause(), Ok(()));
+            assert_eq!(token.transfer_tokens(accounts.bob, 10), Ok(()));
+            assert_eq!(token.balance_of(accounts.bob), 10);
+        }
+
+        #[ink::test]
+        fn frozen_account_can_receive_but_not_send() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            token.mint(100, accounts.bob).unwrap();
+
+            set_caller(accounts.bob);
+            assert_eq!(token.freeze_account(accounts.bob), Err(Error::MissingRole));
+            token.approve(accounts.alice, 50).unwrap();
+            set_caller(accounts.alice);
+            assert_eq!(token.freeze_account(accounts.bob), Ok(()));
+            assert!(token.is_frozen(accounts.bob));
+
+            assert_eq!(
+                token.transfer_from(accounts.bob, accounts.charlie, 10, Vec::new()),
+                Err(PSP22Error::Custom(String::from("AccountFrozen")))
+            );
+            assert_eq!(token.burn_tokens(accounts.bob, 10), Err(Error::AccountFrozen));
+            assert_eq!(token.mint(10, accounts.bob), Ok(()));
+            token.mint(10, accounts.alice).unwrap();
+            assert_eq!(token.transfer_tokens(accounts.bob, 10), Ok(()));
+            assert_eq!(token.balance_of(accounts.bob), 120);
+
+            set_caller(accounts.bob);
+            assert_eq!(token.transfer_tokens(accounts.charlie, 10), Err(Error::AccountFrozen));
+            let mut transfers = Vec::new();
+            transfers.push((accounts.charlie, 10));
+            assert_eq!(token.batch_transfer(transfers), Err(Error::AccountFrozen));
+            assert_eq!(token.unfreeze_account(accounts.bob), Err(Error::MissingRole));
+            assert_eq!(token.balance_of(accounts.bob), 120);
+
+            set_caller(accounts.alice);
+            assert_eq!(token.unfreeze_account(accounts.bob), Ok(()));
+            set_caller(accounts.bob);
+            assert_eq!(token.transfer_tokens(accounts.charlie, 10), Ok(()));
+            assert_eq!(token.balance_of(accounts.charlie), 10);
+        }
+
+        fn advance_blocks(count: u32) {
+            for _ in 0..count {
+                ink_env::test::advance_block::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot advance block");
+            }
+        }
+
+        #[ink::test]
+        fn mint_is_limited_by_cap() {
+            let accounts = default_accounts();
+            let mut token = Token::new(String::from("Wrapped"), String::from("WRP"), 18, Some(100), CHAIN_ID);
+            token.grant_role(MINTER, accounts.alice).unwrap();
+            assert_eq!(token.mint(100, accounts.alice), Ok(()));
+            assert_eq!(token.mint(1, accounts.bob), Err(Error::CapExceeded));
+            assert_eq!(token.total_supply(), 100);
+        }
+
+        #[ink::test]
+        fn mint_quota_resets_after_period() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            assert_eq!(token.set_mint_quota(accounts.alice, 100, 0), Err(Error::InvalidQuota));
+            assert_eq!(token.set_mint_quota(accounts.alice, 100, 10), Ok(()));
+            assert_eq!(token.mint(60, accounts.alice), Ok(()));
+            assert_eq!(token.mint(41, accounts.alice), Err(Error::QuotaExceeded));
+            assert_eq!(token.remaining_mint_quota(accounts.alice), Some(40));
+
+            advance_blocks(9);
+            assert_eq!(token.mint(41, accounts.alice), Err(Error::QuotaExceeded));
+            advance_blocks(1);
+            assert_eq!(token.remaining_mint_quota(accounts.alice), Some(100));
+            assert_eq!(token.mint(100, accounts.alice), Ok(()));
+            assert_eq!(token.balance_of(accounts.alice), 160);
+
+            assert_eq!(token.remove_mint_quota(accounts.alice), Ok(()));
+            assert_eq!(token.remaining_mint_quota(accounts.alice), None);
+            assert_eq!(token.mint(1000, accounts.alice), Ok(()));
+        }
+
+        #[ink::test]
+        fn mint_quota_window_rolls_with_every_block() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            token.set_mint_quota(accounts.alice, 100, 10).unwrap();
+            token.mint(60, accounts.alice).unwrap();
+            advance_blocks(5);
+            token.mint(30, accounts.alice).unwrap();
+            token.mint(10, accounts.alice).unwrap();
+            assert_eq!(token.remaining_mint_quota(accounts.alice), Some(0));
+
+            // only the 60 of block 0 has left the window at block 10
+            advance_blocks(5);
+            assert_eq!(token.remaining_mint_quota(accounts.alice), Some(60));
+            assert_eq!(token.mint(61, accounts.alice), Err(Error::QuotaExceeded));
+            assert_eq!(token.mint(60, accounts.alice), Ok(()));
+
+            // blocks 5 to 14 hold 40 + 60, nothing more fits until block 15
+            advance_blocks(4);
+            assert_eq!(token.mint(1, accounts.alice), Err(Error::QuotaExceeded));
+            advance_blocks(1);
+            assert_eq!(token.remaining_mint_quota(accounts.alice), Some(40));
+            assert_eq!(token.mint(40, accounts.alice), Ok(()));
+            assert_eq!(token.balance_of(accounts.alice), 200);
+        }
+
+        #[ink::test]
+        fn transfer_from_spends_allowance() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            token.mint(100, accounts.alice).unwrap();
+            assert_eq!(token.approve(accounts.bob, 50), Ok(()));
+            assert_eq!(token.allowance(accounts.alice, accounts.bob), 50);
+
+            set_caller(accounts.bob);
+            assert_eq!(
+                token.transfer_from(accounts.alice, accounts.charlie, 60, Vec::new()),
+                Err(PSP22Error::InsufficientAllowance)
+            );
+            assert_eq!(token.transfer_from(accounts.alice, accounts.charlie, 30, Vec::new()), Ok(()));
+            assert_eq!(token.allowance(accounts.alice, accounts.bob), 20);
+            assert_eq!(token.balance_of(accounts.alice), 70);
+            assert_eq!(token.balance_of(accounts.charlie), 30);
+
+            set_caller(accounts.alice);
+            token.approve(accounts.bob, 100).unwrap();
+            set_caller(accounts.bob);
+            assert_eq!(
+                token.transfer_from(accounts.alice, accounts.charlie, 80, Vec::new()),
+                Err(PSP22Error::InsufficientBalance)
+            );
+        }
+
+        #[ink::test]
+        fn allowance_increases_and_decreases() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            assert_eq!(token.increase_allowance(accounts.bob, 10), Ok(()));
+            assert_eq!(token.increase_allowance(accounts.bob, 5), Ok(()));
+            assert_eq!(token.allowance(accounts.alice, accounts.bob), 15);
+            assert_eq!(
+                token.increase_allowance(accounts.bob, u128::MAX),
+                Err(PSP22Error::Custom(String::from("Overflow")))
+            );
+            assert_eq!(token.decrease_allowance(accounts.bob, 16), Err(PSP22Error::InsufficientAllowance));
+            assert_eq!(token.decrease_allowance(accounts.bob, 15), Ok(()));
+            assert_eq!(token.allowance(accounts.alice, accounts.bob), 0);
+        }
+
+        type Event = <Token as ::ink_lang::BaseEvent>::Type;
+
+        fn recorded_events() -> Vec<Event> {
+            ink_env::test::recorded_events()
+                .map(|event| <Event as scale::Decode>::decode(&mut &event.data[..]).expect("Invalid event"))
+                .collect()
+        }
+
+        fn assert_transfer(event: &Event, expected_from: Option<AccountId>, expected_to: Option<AccountId>, expected_value: u128) {
+            match event {
+                Event::Transfer(Transfer { from, to, value }) => {
+                    assert_eq!(*from, expected_from);
+                    assert_eq!(*to, expected_to);
+                    assert_eq!(*value, expected_value);
+                }
+                _ => panic!("expected a Transfer event"),
+            }
+        }
+
+        fn assert_approval(event: &Event, expected_owner: AccountId, expected_spender: AccountId, expected_value: u128) {
+            match event {
+                Event::Approval(Approval { owner, spender, value }) => {
+                    assert_eq!(*owner, expected_owner);
+                    assert_eq!(*spender, expected_spender);
+                    assert_eq!(*value, expected_value);
+                }
+                _ => panic!("expected an Approval event"),
+            }
+        }
+
+        #[ink::test]
+        fn balance_changes_emit_transfer() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            let start = recorded_events().len();
+            token.mint(100, accounts.alice).unwrap();
+            token.transfer_tokens(accounts.bob, 30).unwrap();
+            token.burn_tokens(accounts.alice, 20).unwrap();
+
+            let events = recorded_events();
+            assert_eq!(events.len(), start + 3);
+            assert_transfer(&events[start], None, Some(accounts.alice), 100);
+            assert_transfer(&events[start + 1], Some(accounts.alice), Some(accounts.bob), 30);
+            assert_transfer(&events[start + 2], Some(accounts.alice), None, 20);
+        }
+
+        #[ink::test]
+        fn allowance_changes_emit_approval() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            token.mint(100, accounts.alice).unwrap();
+            let start = recorded_events().len();
+            token.approve(accounts.bob, 50).unwrap();
+            set_caller(accounts.bob);
+            token.transfer_from(accounts.alice, accounts.charlie, 30, Vec::new()).unwrap();
+
+            let events = recorded_events();
+            assert_eq!(events.len(), start + 3);
+            assert_approval(&events[start], accounts.alice, accounts.bob, 50);
+            assert_transfer(&events[start + 1], Some(accounts.alice), Some(accounts.charlie), 30);
+            assert_approval(&events[start + 2], accounts.alice, accounts.bob, 20);
+        }
+
+        #[ink::test]
+        fn batch_transfer_is_all_or_nothing() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            token.mint(100, accounts.alice).unwrap();
+
+            let mut transfers = Vec::new();
+            transfers.push((accounts.bob, 60));
+            transfers.push((accounts.charlie, 60));
+            assert_eq!(token.batch_transfer(transfers), Err(Error::NotEnoughMoney));
+            assert_eq!(token.balance_of(accounts.alice), 100);
+            assert_eq!(token.balance_of(accounts.bob), 0);
+
+            let mut transfers = Vec::new();
+            transfers.push((accounts.bob, 60));
+            transfers.push((accounts.charlie, 40));
+            assert_eq!(token.batch_transfer(transfers), Ok(()));
+            assert_eq!(token.balance_of(accounts.alice), 0);
+            assert_eq!(token.balance_of(accounts.bob), 60);
+            assert_eq!(token.balance_of(accounts.charlie), 40);
+        }
+
+        #[ink::test]
+        fn batch_mint_checks_cap_and_quota_on_the_sum() {
+            let accounts = default_accounts();
+            let mut token = Token::new(String::from("Wrapped"), String::from("WRP"), 18, Some(100), CHAIN_ID);
+            token.grant_role(MINTER, accounts.alice).unwrap();
+            token.set_mint_quota(accounts.alice, 80, 10).unwrap();
+
+            let mut mints = Vec::new();
+            mints.push((accounts.bob, 50));
+            mints.push((accounts.charlie, 40));
+            assert_eq!(token.batch_mint(mints), Err(Error::QuotaExceeded));
+            assert_eq!(token.balance_of(accounts.bob), 0);
+            assert_eq!(token.total_supply(), 0);
+            assert_eq!(token.remaining_mint_quota(accounts.alice), Some(80));
+
+            token.remove_mint_quota(accounts.alice).unwrap();
+            let mut mints = Vec::new();
+            mints.push((accounts.bob, 60));
+            mints.push((accounts.charlie, 41));
+            assert_eq!(token.batch_mint(mints), Err(Error::CapExceeded));
+            assert_eq!(token.balance_of(accounts.bob), 0);
+            assert_eq!(token.total_supply(), 0);
+
+            let mut mints = Vec::new();
+            mints.push((accounts.bob, 60));
+            mints.push((accounts.charlie, 40));
+            assert_eq!(token.batch_mint(mints), Ok(()));
+            assert_eq!(token.balance_of(accounts.bob), 60);
+            assert_eq!(token.balance_of(accounts.charlie), 40);
+            assert_eq!(token.total_supply(), 100);
+        }
+
+        #[ink::test]
+        fn transfer_and_call_checks_fail_before_calling() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            token.grant_role(PAUSER, accounts.alice).unwrap();
+            token.mint(100, accounts.alice).unwrap();
+            assert_eq!(token.transfer_and_call(accounts.bob, 101, Vec::new()), Err(Error::NotEnoughMoney));
+
+            token.pause().unwrap();
+            assert_eq!(token.transfer_and_call(accounts.bob, 10, Vec::new()), Err(Error::Paused));
+            token.unpause().unwrap();
+
+            token.freeze_account(accounts.alice).unwrap();
+            assert_eq!(token.transfer_and_call(accounts.bob, 10, Vec::new()), Err(Error::AccountFrozen));
+            assert_eq!(token.balance_of(accounts.alice), 100);
+            assert_eq!(token.balance_of(accounts.bob), 0);
+        }
+
+        #[ink::test]
+        fn flash_loan_checks_fail_before_minting() {
+            let accounts = default_accounts();
+            let mut token = Token::new(String::from("Wrapped"), String::from("WRP"), 18, Some(100), CHAIN_ID);
+            token.grant_role(PAUSER, accounts.alice).unwrap();
+            assert_eq!(token.max_flash_loan(), 100);
+            assert_eq!(token.flash_loan(accounts.bob, 101, Vec::new()), Err(Error::CapExceeded));
+            token.pause().unwrap();
+            assert_eq!(token.flash_loan(accounts.bob, 50, Vec::new()), Err(Error::Paused));
+            assert_eq!(token.balance_of(accounts.bob), 0);
+            assert_eq!(token.total_supply(), 0);
+        }
+
+        #[ink::test]
+        fn flash_loan_repayment_needs_allowance_for_amount_and_fee() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            token.set_flash_fee_bps(100).unwrap();
+            token.set_treasury(accounts.django).unwrap();
+            let fee = token.flash_fee(1000).unwrap();
+            assert_eq!(fee, 10);
+            let this = ink_env::account_id::<ink_env::DefaultEnvironment>()
+                .unwrap_or([0x0; 32].into());
+
+            // the loan as the receiver sees it in `on_flash_loan`, plus the fee it earned
+            token.mint(1000 + fee, accounts.bob).unwrap();
+            set_caller(accounts.bob);
+            token.approve(this, 1000).unwrap();
+            assert_eq!(
+                token.repay_flash_loan(accounts.bob, 1000, fee, 1000 + fee),
+                Err(Error::InsufficientAllowance)
+            );
+            assert_eq!(token.balance_of(accounts.bob), 1000 + fee);
+            assert_eq!(token.total_supply(), 1000 + fee);
+
+            token.approve(this, 1000 + fee).unwrap();
+            assert_eq!(token.repay_flash_loan(accounts.bob, 1000, fee, 1000 + fee), Ok(()));
+            assert_eq!(token.balance_of(accounts.bob), 0);
+            assert_eq!(token.balance_of(accounts.django), fee);
+            assert_eq!(token.total_supply(), fee);
+            assert_eq!(token.allowance(accounts.bob, this), 0);
+        }
+
+        #[ink::test]
+        fn balances_are_kept_at_snapshots() {
+            let accounts = default_accounts();
+            let mut token = new_token();
+            token.mint(100, accounts.alice).unwrap();
+            assert_eq!(token.snapshot(), Ok(1));
+            token.transfer_tokens(accounts.bob, 30).unwrap();
+            assert_eq!(token.snapshot(), Ok(2));
+            token.burn_tokens(accounts.alice, 70).unwrap();
+
+            assert_eq!(token.balance_of_at(accounts.alice, 1), Ok(100));
+            assert_eq!(token.balance_of_at(accounts.bob, 1), Ok(0));
+            assert_eq!(token.total_supply_at(1), Ok(100));
+            assert_eq!(token.balance_of_at(accounts.alice, 2), Ok(70));
+            assert_eq!(token.balance_of_at(accounts.bob, 2), Ok(30));
+            assert_eq!(token.total_supply_at(2), Ok(100));
+            assert_eq!(token.balance_of(accounts.alice), 0);
+            assert_eq!(token.total_supply(), 30);
+            assert_eq!(token.balance_of_at(accounts.alice, 3), Err(Error::InvalidSnapshot));
+        }
+
+        #[ink::test]
+        fn permit_sets_allowance_once() {
+            use web3::signing::{Key, SecretKey, SecretKeyRef};
+
+            let accounts = default_accounts();
+            let mut token = new_token();
+            let key = SecretKey::from_slice(&[7u8;32]).unwrap();
+            let key = SecretKeyRef::new(&key);
+            let mut owner = [0u8;32];
+            owner[0..20].copy_from_slice(key.address().as_bytes());
+            let owner = AccountId::from(owner);
+
+            // a permit signed for another chain does not verify
+            let other_chain = Token::new(String::from("Wrapped"), String::from("WRP"), 18, None, CHAIN_ID + 1);
+            assert_ne!(other_chain.domain_separator(), token.domain_separator());
+            let hash = other_chain.hash_permit(owner, accounts.bob, 100, u64::MAX);
+            let sig = key.sign(&hash, None).unwrap();
+            assert_eq!(
+                token.permit(owner, accounts.bob, 100, u64::MAX, sig.v, sig.r.into(), sig.s.into()),
+                Err(Error::InvalidSignature)
+            );
+
+            let hash = token.hash_permit(owner, accounts.bob, 100, u64::MAX);
+            let sig = key.sign(&hash, None).unwrap();
+            let r: [u8;32] = sig.r.into();
+            let s: [u8;32] = sig.s.into();
+            assert_eq!(token.permit(owner, accounts.bob, 100, u64::MAX, sig.v, r, s), Ok(()));
+            assert_eq!(token.allowance(owner, accounts.bob), 100);
+            assert_eq!(token.nonces(owner), 1);
+
+            // the nonce is part of the hash, so the same signature can not be replayed
+            assert_eq!(
+                token.permit(owner, accounts.bob, 100, u64::MAX, sig.v, r, s),
+                Err(Error::InvalidSignature)
+            );
+        }
     }
 }